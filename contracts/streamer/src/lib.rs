@@ -1,679 +1,4592 @@
 #![no_std]
+// Contract entry points mirror the client-facing argument lists.
+#![allow(clippy::too_many_arguments)]
+// Events use tuple topics via `Events::publish`, which SDK 23 marks deprecated.
+#![allow(deprecated)]
 
 use soroban_sdk::token::Client as TokenClient;
 use soroban_sdk::{
-    contract, contractimpl, contracttype, symbol_short, Address, Env, Map, String, Vec,
+    contract, contracterror, contractimpl, contracttype, panic_with_error, symbol_short, Address,
+    Bytes, BytesN, Env, IntoVal, Map, String, Symbol, TryFromVal, Val, Vec,
 };
 
 const MAX_TITLE_LEN: u32 = 120;
 const MAX_DESCRIPTION_LEN: u32 = 1024;
+const BPS_DENOMINATOR: i128 = 10_000;
+const MAX_FEE_BPS: u32 = 1_000; // 10%
+const MAX_PAGE_LIMIT: u32 = 50; // Max records returned by paged queries
+const NATIVE_DECIMALS: u32 = 7; // XLM atomic unit is the stroop (10^-7 XLM)
+const RATE_SCALE: i128 = 1_000_000_000; // Reported flow rates are atomic units per second times this
 
-fn normalize_optional_text(input: Option<String>, max_len: u32) -> Option<String> {
+// Calendar-free billing periods used by `Period`; a "month" is 30 days and a "year" 365
+const SECONDS_PER_DAY: u64 = 24 * 60 * 60;
+const SECONDS_PER_WEEK: u64 = 7 * SECONDS_PER_DAY;
+const SECONDS_PER_MONTH: u64 = 30 * SECONDS_PER_DAY;
+const SECONDS_PER_YEAR: u64 = 365 * SECONDS_PER_DAY;
+
+// Contract version, bumped with each release that changes behaviour or storage layout. `init`
+// and `migrate` record it under `DataKey::Version`; deployments initialised before versioning
+// have no entry and are treated as version 0. Version 2 added fields to `Stream` and
+// `Subscription` (see `StreamV1`/`SubscriptionV1` for what came before).
+const VERSION: u32 = 2;
+const DEFAULT_STALE_SECONDS: u64 = 30 * SECONDS_PER_DAY; // Uncharged time before escrow is reclaimable
+const MAX_SWEEP_WITHDRAWALS: u32 = 25; // Max streams paid out by one withdraw_all_received call
+const DEFAULT_MAX_STREAMS_PER_LEDGER: u32 = 10; // Per-sender creation cap, against index spam
+const MAX_BATCH_SUBSCRIPTIONS: u32 = 20; // Max subscriptions set up by one create_subscriptions call
+const MAX_STREAM_ACTIVITY: u32 = 10; // Withdrawals kept per stream for get_recent_stream_activity
+const MAX_MIGRATE_RECORDS: u32 = 25; // Max stored streams/subscriptions checked by one migrate call
+
+// Contract types are stored as field-name maps, so the field count tells the legacy layouts
+// `migrate` converts apart from each other and from the current one.
+const STREAM_V0_FIELDS: u32 = 12;
+const STREAM_V1_FIELDS: u32 = 19;
+const SUBSCRIPTION_V0_FIELDS: u32 = 11;
+const SUBSCRIPTION_V1_FIELDS: u32 = 13;
+
+// Persistent entries are bumped to ~30 days of ledgers (5s each) whenever they are touched,
+// once their remaining TTL drops below ~29 days.
+const DAY_IN_LEDGERS: u32 = 17_280;
+const PERSISTENT_BUMP_AMOUNT: u32 = 30 * DAY_IN_LEDGERS;
+const PERSISTENT_LIFETIME_THRESHOLD: u32 = PERSISTENT_BUMP_AMOUNT - DAY_IN_LEDGERS;
+
+fn normalize_optional_text(input: Option<String>, max_len: u32) -> Result<Option<String>, Error> {
     match input {
         Some(value) => {
-            if value.len() == 0 {
-                None
+            if value.is_empty() {
+                Ok(None)
             } else {
                 if value.len() > max_len {
-                    panic!();
+                    return Err(Error::InvalidParameters);
                 }
-                Some(value)
+                Ok(Some(value))
             }
         }
-        None => None,
+        None => Ok(None),
     }
 }
 
-/// Error codes
-#[contracttype]
-#[derive(Clone, Copy, Debug, Eq, PartialEq)]
-pub enum Error {
-    AlreadyInitialized = 1,
-    InvalidParameters = 2,
-    ContractInsufficientBalance = 3,
-    StreamNotFound = 4,
-    StreamInactive = 5,
-    NothingToWithdraw = 6,
-    SubscriptionNotFound = 7,
-    SubscriptionInactive = 8,
-    NotDueYet = 9,
-    InsufficientContractBalance = 10,
-    NotInitialized = 11,
+/// Extend the TTL of a persistent entry so it isn't archived while still in use.
+/// Missing entries are ignored.
+fn extend_persistent(env: &Env, key: &DataKey) {
+    let storage = env.storage().persistent();
+    if storage.has(key) {
+        storage.extend_ttl(key, PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    }
 }
 
-/// Data keys in storage
-#[contracttype]
-#[derive(Clone)]
-pub enum DataKey {
-    PlatformAdmin,
-    NextStreamId,
-    StreamKey(u32), // stream_id -> Stream
-    NextSubscriptionId,
-    SubscriptionKey(u32),               // subscription_id -> Subscription
-    TokenContract,                      // Address (optional global token id if you want a default)
-    UserSentStreams(Address), // user address -> Vec<u32> (stream IDs where user is sender)
-    UserReceivedStreams(Address), // user address -> Vec<u32> (stream IDs where user is recipient)
-    UserSubscriptions(Address), // user address -> Vec<u32> (subscription IDs where user is subscriber)
-    UserReceivedSubscriptions(Address), // user address -> Vec<u32> (subscription IDs where user is receiver)
+/// Extend the TTL of the contract instance and the global counters/config it relies on.
+fn extend_globals(env: &Env) {
+    env.storage()
+        .instance()
+        .extend_ttl(PERSISTENT_LIFETIME_THRESHOLD, PERSISTENT_BUMP_AMOUNT);
+    extend_persistent(env, &DataKey::PlatformAdmin);
+    extend_persistent(env, &DataKey::NextStreamId);
+    extend_persistent(env, &DataKey::NextSubscriptionId);
+    extend_persistent(env, &DataKey::Version);
+    extend_persistent(env, &DataKey::ActiveStreams);
+    extend_persistent(env, &DataKey::ActiveSubscriptions);
 }
 
-/// A streaming payment: continuous rate-based escrow
-#[contracttype]
-#[derive(Clone)]
-pub struct Stream {
-    pub id: u32,
-    pub sender: Address,
-    pub recipients: Vec<Address>, // Multiple recipients (changed from single Address)
-    pub token_contract: Address,
-    // Per-recipient rate in atomic units per second, derived from amount-per-period / period_seconds
-    pub recipient_rate_per_second: Map<Address, i128>,
-    pub deposit: i128,   // total deposited initially (remaining is derived)
-    pub start_time: u64, // ledger timestamp seconds
-    pub recipient_last_withdraw: Map<Address, u64>, // Per-recipient last withdrawal time
-    pub recipient_total_withdrawn: Map<Address, i128>, // Per-recipient total withdrawn amount
-    pub is_active: bool,
-    pub title: Option<String>,
-    pub description: Option<String>,
+/// Clamp a `start`/`limit` page request to `[start, end)` indexes within `len` items.
+/// Out-of-range starts yield an empty range; `limit` is capped at `MAX_PAGE_LIMIT`.
+fn page_range(len: u32, start: u32, limit: u32) -> (u32, u32) {
+    let limit = core::cmp::min(limit, MAX_PAGE_LIMIT);
+    let start = core::cmp::min(start, len);
+    let end = core::cmp::min(start.saturating_add(limit), len);
+    (start, end)
 }
 
-/// A recurring subscription (pull/payments at intervals)
-#[contracttype]
-#[derive(Clone)]
-pub struct Subscription {
-    pub id: u32,
-    pub subscriber: Address,
-    pub receiver: Address,
-    pub token_contract: Address,
-    pub amount_per_interval: i128,
-    pub interval_seconds: u64,
-    pub next_payment_time: u64,
-    pub active: bool,
-    pub balance: i128, // Escrowed balance for this subscription (isolated from other subscriptions)
-    pub title: Option<String>,
-    pub description: Option<String>,
+/// Clamp a walk over the ID space to `[start, end)`: IDs start at 1, stop before `next_id`,
+/// and at most `limit` (capped at `MAX_PAGE_LIMIT`) are visited.
+fn id_range(start: u32, limit: u32, next_id: u32) -> (u32, u32) {
+    let start = core::cmp::max(start, 1);
+    let limit = core::cmp::min(limit, MAX_PAGE_LIMIT);
+    let end = core::cmp::min(start.saturating_add(limit), next_id);
+    (start, end)
 }
 
-#[contract]
-pub struct Streamer;
-
-#[contractimpl]
-impl Streamer {
-    /// Initialize platform admin and optional default token contract.
-    /// Call once.
-    pub fn init(env: Env, platform_admin: Address, default_token: Option<Address>) {
-        if env
+/// Hydrate one page of stream IDs into `Stream` records, skipping missing entries.
+fn load_streams_page(env: &Env, ids: &Vec<u32>, start: u32, limit: u32) -> Vec<Stream> {
+    let (start, end) = page_range(ids.len(), start, limit);
+    let mut streams = Vec::new(env);
+    for i in start..end {
+        let stream_id = ids.get(i).unwrap();
+        if let Some(stream) = env
             .storage()
             .persistent()
-            .get::<_, Address>(&DataKey::PlatformAdmin)
-            .is_some()
+            .get::<_, Stream>(&DataKey::StreamKey(stream_id))
         {
-            panic!();
+            streams.push_back(stream);
         }
-        env.storage()
-            .persistent()
-            .set(&DataKey::PlatformAdmin, &platform_admin);
-        env.storage()
-            .persistent()
-            .set(&DataKey::NextStreamId, &1u32);
-        env.storage()
+    }
+    streams
+}
+
+/// Hydrate one page of subscription IDs into `Subscription` records, skipping missing entries.
+fn load_subscriptions_page(env: &Env, ids: &Vec<u32>, start: u32, limit: u32) -> Vec<Subscription> {
+    let (start, end) = page_range(ids.len(), start, limit);
+    let mut subscriptions = Vec::new(env);
+    for i in start..end {
+        let subscription_id = ids.get(i).unwrap();
+        if let Some(subscription) = env
+            .storage()
             .persistent()
-            .set(&DataKey::NextSubscriptionId, &1u32);
-        if let Some(t) = default_token {
-            env.storage().persistent().set(&DataKey::TokenContract, &t);
+            .get::<_, Subscription>(&DataKey::SubscriptionKey(subscription_id))
+        {
+            subscriptions.push_back(subscription);
         }
     }
+    subscriptions
+}
 
-    // ===========================
-    // STREAMING: create / withdraw / cancel
-    // ===========================
+/// Remove `id` from the ID index stored under `key`, if present.
+fn remove_from_index(env: &Env, key: &DataKey, id: u32) {
+    let ids: Option<Vec<u32>> = env.storage().persistent().get(key);
+    if let Some(mut ids) = ids {
+        if let Some(index) = ids.first_index_of(id) {
+            ids.remove(index);
+            env.storage().persistent().set(key, &ids);
+        }
+    }
+}
 
-    /// Create a stream. Transfers `deposit` tokens from the sender to this contract
-    /// and registers a new payment stream with multiple recipients.
-    /// Each recipient receives the full `rate_per_second` (multiplicative model).
-    ///
-    /// Returns the stream id.
-    pub fn create_stream(
-        env: Env,
-        sender: Address,
-        recipients: Vec<Address>,
-        token_contract: Address,
-        amounts_per_period: Vec<i128>, // atomic units for each recipient
-        period_seconds: u64,           // e.g., 30 days in seconds
-        deposit: i128,
-        title: Option<String>,
-        description: Option<String>,
-    ) -> u32 {
-        // auth
-        sender.require_auth();
+/// Timestamp up to which a stream accrues: frozen at `paused_at` while paused, clamped to
+/// `end_time` for fixed-term streams, and never earlier than `start_time` so scheduled
+/// streams accrue nothing before they begin.
+fn accrual_time(stream: &Stream, now: u64) -> u64 {
+    let mut t = match stream.paused_at {
+        Some(paused_at) => paused_at,
+        None => now,
+    };
+    if let Some(end_time) = stream.end_time {
+        t = core::cmp::min(t, end_time);
+    }
+    core::cmp::max(t, stream.start_time)
+}
 
-        // Validate inputs
-        if recipients.len() == 0 {
-            panic!(); // At least one recipient required
-        }
+/// Whether the vesting cliff (if any) has passed at ledger time `now`. Accrual still builds
+/// up from `start_time` before the cliff; it just can't be withdrawn yet.
+fn cliff_reached(stream: &Stream, now: u64) -> bool {
+    match stream.cliff_time {
+        Some(cliff_time) => now >= cliff_time,
+        None => true,
+    }
+}
 
-        // Check lengths and duplicates
-        if recipients.len() != amounts_per_period.len() {
-            panic!();
-        }
-        for i in 0..recipients.len() {
-            for j in (i + 1)..recipients.len() {
-                if recipients.get(i).unwrap() == recipients.get(j).unwrap() {
-                    panic!(); // Duplicate recipient found
-                }
-            }
+/// Whether `recipient` may accrue: always, unless the stream requires acceptance and they
+/// haven't called `accept_stream` yet.
+fn recipient_accepted(stream: &Stream, recipient: &Address) -> bool {
+    !stream.require_acceptance || stream.accepted.get(recipient.clone()).unwrap_or(false)
+}
+
+/// Sum of per-recipient amounts streamed each `period_seconds`.
+fn total_amount_per_period(stream: &Stream) -> i128 {
+    let mut total: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        let amt = stream.recipient_amount_per_period.get(r).unwrap_or(0i128);
+        total = total.saturating_add(amt);
+    }
+    total
+}
+
+/// Splits `amount` across recipients in proportion to their rates and adds each share to
+/// their allocation. Proportional shares mean every recipient's allocation runs out at the
+/// same time. The first recipient absorbs the integer-division remainder.
+/// A share too large to compute is reported as `Error::ArithmeticOverflow`, not clamped.
+fn allocate_by_rate(stream: &mut Stream, amount: i128) -> Result<(), Error> {
+    // Shares use the exact amount-per-period rather than the truncated per-second rate
+    let total_amount = total_amount_per_period(stream);
+    if total_amount <= 0 || stream.recipients.is_empty() {
+        return Ok(());
+    }
+    let mut allocated: i128 = 0i128;
+    for i in 1..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        let amt = stream
+            .recipient_amount_per_period
+            .get(r.clone())
+            .unwrap_or(0i128);
+        let share = amount.checked_mul(amt).ok_or(Error::ArithmeticOverflow)? / total_amount;
+        let current = stream.recipient_allocation.get(r.clone()).unwrap_or(0i128);
+        stream.recipient_allocation.set(
+            r,
+            current
+                .checked_add(share)
+                .ok_or(Error::ArithmeticOverflow)?,
+        );
+        allocated = allocated
+            .checked_add(share)
+            .ok_or(Error::ArithmeticOverflow)?;
+    }
+    let first = stream.recipients.get(0).unwrap();
+    let current = stream
+        .recipient_allocation
+        .get(first.clone())
+        .unwrap_or(0i128);
+    let rest = amount
+        .checked_sub(allocated)
+        .and_then(|rest| current.checked_add(rest))
+        .ok_or(Error::ArithmeticOverflow)?;
+    stream.recipient_allocation.set(first, rest);
+    Ok(())
+}
+
+/// Part of a recipient's allocation they have not withdrawn yet.
+fn recipient_unwithdrawn(stream: &Stream, recipient: &Address) -> i128 {
+    let allocation = stream
+        .recipient_allocation
+        .get(recipient.clone())
+        .unwrap_or(0i128);
+    let withdrawn = stream
+        .recipient_total_withdrawn
+        .get(recipient.clone())
+        .unwrap_or(0i128);
+    core::cmp::max(allocation.saturating_sub(withdrawn), 0i128)
+}
+
+/// Accrual numerator for `recipient` since their last withdrawal, in atomic units times
+/// `period_seconds`. Dividing by the period only when paying out (and carrying the
+/// remainder forward) means no truncation dust is lost between withdrawals.
+/// Overflow (e.g. an extreme rate over a very long time) is reported as
+/// `Error::ArithmeticOverflow` rather than clamped.
+fn recipient_accrual_numerator(
+    stream: &Stream,
+    recipient: &Address,
+    now: u64,
+) -> Result<i128, Error> {
+    // Nothing accrues until the gate is released, nor for recipients who still have to
+    // accept the stream
+    if stream.gated || !recipient_accepted(stream, recipient) {
+        return Ok(0i128);
+    }
+    let now = accrual_time(stream, now);
+    let last_withdraw = stream
+        .recipient_last_withdraw
+        .get(recipient.clone())
+        .unwrap_or(stream.start_time);
+    if now <= last_withdraw {
+        return Ok(0i128);
+    }
+    let amt = stream
+        .recipient_amount_per_period
+        .get(recipient.clone())
+        .unwrap_or(0i128);
+    let carry = stream
+        .recipient_accrual_remainder
+        .get(recipient.clone())
+        .unwrap_or(0i128);
+    let elapsed = (now - last_withdraw) as i128;
+    elapsed
+        .checked_mul(amt)
+        .and_then(|accrued| accrued.checked_add(carry))
+        .ok_or(Error::ArithmeticOverflow)
+}
+
+/// Funds accrued to `recipient` since their last withdrawal, capped by what is left of their
+/// own allocation so other recipients' withdrawals never eat into it.
+fn recipient_accrued(stream: &Stream, recipient: &Address, now: u64) -> Result<i128, Error> {
+    let accrued =
+        recipient_accrual_numerator(stream, recipient, now)? / stream.period_seconds as i128;
+    Ok(core::cmp::min(
+        accrued,
+        recipient_unwithdrawn(stream, recipient),
+    ))
+}
+
+/// Rate at which `recipient` is accruing from `stream` right now, in atomic units per second
+/// scaled by `RATE_SCALE` so per-hour and slower rates don't truncate to 0: 0 if the stream
+/// is inactive, paused, gated or ended, if they still have to accept it or are not a
+/// recipient, or once their allocation has fully accrued.
+fn recipient_flow_rate(stream: &Stream, recipient: &Address, ledger_now: u64) -> i128 {
+    if !stream.is_active || stream.paused_at.is_some() || stream.gated {
+        return 0i128;
+    }
+    if let Some(end_time) = stream.end_time {
+        if ledger_now >= end_time {
+            return 0i128;
         }
+    }
+    if !recipient_accepted(stream, recipient) {
+        return 0i128;
+    }
+    let rate = match stream.recipient_amount_per_period.get(recipient.clone()) {
+        Some(amount) => amount.saturating_mul(RATE_SCALE) / stream.period_seconds as i128,
+        None => return 0i128,
+    };
+    let now = accrual_time(stream, ledger_now);
+    match recipient_accrued(stream, recipient, now) {
+        Ok(accrued) if recipient_unwithdrawn(stream, recipient) - accrued > 0 => rate,
+        _ => 0i128,
+    }
+}
 
-        if period_seconds == 0 || deposit <= 0 {
-            panic!();
+/// Combined rate at which `stream` is paying out right now: `recipient_flow_rate` summed
+/// over its recipients (so also scaled by `RATE_SCALE`).
+fn stream_flow_rate(stream: &Stream, ledger_now: u64) -> i128 {
+    let mut total: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        total = total.saturating_add(recipient_flow_rate(stream, &r, ledger_now));
+    }
+    total
+}
+
+/// Deposit that has not yet accrued to any recipient.
+fn stream_remaining(stream: &Stream, now: u64) -> Result<i128, Error> {
+    let mut remaining: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        let unaccrued = recipient_unwithdrawn(stream, &r) - recipient_accrued(stream, &r, now)?;
+        remaining = remaining.saturating_add(unaccrued);
+    }
+    Ok(remaining)
+}
+
+/// Allocation still earmarked for recipients of a stream that closed on its own (every
+/// withdrawal done, `deposit` untouched) and that nobody can withdraw any more, e.g.
+/// truncation leftovers from streams exhausted before accrual carried its remainder.
+/// Cancelled and reclaimed streams already refunded everything and report 0.
+fn stream_dust(stream: &Stream) -> i128 {
+    if stream.is_active || stream.deposit == 0 {
+        return 0i128;
+    }
+    let mut dust: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        dust = dust.saturating_add(recipient_unwithdrawn(stream, &r));
+    }
+    dust
+}
+
+/// Auto-renew: once less than `refill_amount` is left to accrue, pull another
+/// `refill_amount` from `refill_from` using the allowance it granted this contract and add
+/// it to the deposit like a top-up. A missing allowance or balance just leaves the stream to
+/// wind down; so does a stream that has already run dry, ended or been frozen. Stores the
+/// refilled stream itself, so callers run it only after their own changes are stored.
+fn try_refill_stream(env: &Env, stream_id: u32, stream: &mut Stream, ledger_now: u64) {
+    let from = match stream.refill_from.clone() {
+        Some(from) if stream.refill_amount > 0 => from,
+        _ => return,
+    };
+    if !stream.is_active || is_token_frozen(env, &stream.token_contract) {
+        return;
+    }
+    if let Some(end_time) = stream.end_time {
+        if ledger_now >= end_time {
+            return;
         }
+    }
+    let now = accrual_time(stream, ledger_now);
+    match stream_remaining(stream, now) {
+        Ok(remaining) if remaining > 0 && remaining < stream.refill_amount => {}
+        _ => return,
+    }
 
-        // compute start time
-        let start_time: u64 = env.ledger().timestamp();
+    // Work out the extended allocation before pulling anything, so a refill that can't be
+    // accounted for is simply skipped
+    let amount = stream.refill_amount;
+    let mut refilled = stream.clone();
+    refilled.deposit = match stream.deposit.checked_add(amount) {
+        Some(deposit) => deposit,
+        None => return,
+    };
+    if allocate_by_rate(&mut refilled, amount).is_err() {
+        return;
+    }
 
-        // Transfer tokens from sender to contract
-        let token = TokenClient::new(&env, &token_contract);
-        let contract_addr = env.current_contract_address();
+    let token = TokenClient::new(env, &stream.token_contract);
+    let contract_addr = env.current_contract_address();
+    if !matches!(
+        token.try_transfer_from(&contract_addr, &from, &contract_addr, &amount),
+        Ok(Ok(()))
+    ) {
+        return;
+    }
+    *stream = refilled;
+    env.storage()
+        .persistent()
+        .set(&DataKey::StreamKey(stream_id), &*stream);
+    extend_persistent(env, &DataKey::StreamKey(stream_id));
 
-        // Transfer deposit from sender to contract
-        token.transfer(&sender, &contract_addr, &deposit);
+    env.events().publish(
+        (symbol_short!("strm_rfl"), stream_id),
+        (from, amount, stream.deposit, now),
+    );
+}
 
-        // allocate stream id
-        let mut next_id: u32 = env
-            .storage()
-            .persistent()
-            .get(&DataKey::NextStreamId)
-            .unwrap_or(1u32);
-        let stream_id = next_id;
+/// Re-split the not-yet-accrued deposit across the current recipients in proportion to
+/// their rates. Whatever each recipient has already accrued stays earmarked for them, so
+/// this only changes how the future is shared (e.g. after the recipient set changes).
+fn rebalance_allocations(stream: &mut Stream, now: u64) -> Result<(), Error> {
+    let mut pool: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        let accrued = recipient_accrued(stream, &r, now)?;
+        let unaccrued = recipient_unwithdrawn(stream, &r) - accrued;
+        let withdrawn = stream
+            .recipient_total_withdrawn
+            .get(r.clone())
+            .unwrap_or(0i128);
+        stream
+            .recipient_allocation
+            .set(r, withdrawn.saturating_add(accrued));
+        pool = pool.saturating_add(unaccrued);
+    }
+    allocate_by_rate(stream, pool)
+}
 
-        // Create maps for tracking
-        let mut recipient_last_withdraw = Map::new(&env);
-        let mut recipient_total_withdrawn = Map::new(&env);
-        let mut recipient_rate_per_second = Map::new(&env);
+/// Fold everything `recipient` has accrued up to `now` into their carry and re-base their
+/// last-withdraw time at `now`, so a rate change afterwards doesn't reprice past accrual.
+/// The carry is capped at the unwithdrawn allocation.
+fn fold_accrual(stream: &mut Stream, recipient: &Address, now: u64) -> Result<(), Error> {
+    let numerator = recipient_accrual_numerator(stream, recipient, now)?;
+    let cap = recipient_unwithdrawn(stream, recipient)
+        .checked_mul(stream.period_seconds as i128)
+        .ok_or(Error::ArithmeticOverflow)?;
+    stream
+        .recipient_accrual_remainder
+        .set(recipient.clone(), core::cmp::min(numerator, cap));
+    stream.recipient_last_withdraw.set(recipient.clone(), now);
+    Ok(())
+}
+
+/// Projected timestamp at which accrual stops: when the last recipient's allocation is
+/// fully accrued, or `end_time` if that comes first. While paused, the projection assumes
+/// the stream resumes at `now`.
+fn stream_exhaustion_time(stream: &Stream, now: u64) -> Result<u64, Error> {
+    let t = accrual_time(stream, now);
+    let base = if stream.paused_at.is_some() {
+        core::cmp::max(now, stream.start_time)
+    } else {
+        t
+    };
+    let period = stream.period_seconds as i128;
+    let mut seconds_left: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        let amt = stream
+            .recipient_amount_per_period
+            .get(r.clone())
+            .unwrap_or(0i128);
+        if amt <= 0 {
+            continue;
+        }
+        // Numerator still needed before this recipient's allocation is fully accrued
+        let needed = recipient_unwithdrawn(stream, &r)
+            .checked_mul(period)
+            .ok_or(Error::ArithmeticOverflow)?
+            .saturating_sub(recipient_accrual_numerator(stream, &r, now)?);
+        if needed > 0 {
+            seconds_left = core::cmp::max(seconds_left, (needed + amt - 1) / amt);
+        }
+    }
+    let exhaustion = base.saturating_add(core::cmp::min(seconds_left, u64::MAX as i128) as u64);
+    Ok(match stream.end_time {
+        Some(end_time) => core::cmp::min(exhaustion, end_time),
+        None => exhaustion,
+    })
+}
 
-        // Derive per-recipient rate: amount_per_period / period_seconds (integer division)
-        let normalized_title = normalize_optional_text(title, MAX_TITLE_LEN);
-        let normalized_description = normalize_optional_text(description, MAX_DESCRIPTION_LEN);
+/// What a full withdrawal by `recipient` would pay out at ledger time `ledger_now`.
+/// Shared by `withdraw_from_stream` and `preview_withdraw` so the two can't drift apart.
+fn stream_withdrawable(
+    stream: &Stream,
+    recipient: &Address,
+    ledger_now: u64,
+) -> Result<i128, Error> {
+    // A stream awaiting its first acceptance is inactive; report what the recipient must do
+    let awaiting_acceptance =
+        stream.require_acceptance && stream.accepted.is_empty() && stream.deposit > 0;
+    if !stream.is_active && !awaiting_acceptance {
+        return Err(Error::StreamInactive);
+    }
 
-        for i in 0..recipients.len() {
-            let recipient = recipients.get(i).unwrap();
-            let amt = amounts_per_period.get(i).unwrap();
-            if amt <= 0i128 {
-                panic!();
-            }
-            let rate_i: i128 = amt / (period_seconds as i128);
-            if rate_i <= 0i128 {
-                // Too small for given period
-                panic!();
-            }
-            recipient_rate_per_second.set(recipient.clone(), rate_i);
-            // Initialize last withdraw maps (optional; default on read is start_time)
-            // Initialize totals to 0
-            recipient_total_withdrawn.set(recipient.clone(), 0i128);
+    // Verify recipient is in the recipients list
+    let mut is_recipient = false;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        if r == *recipient {
+            is_recipient = true;
+            break;
         }
+    }
+    if !is_recipient {
+        return Err(Error::NotARecipient);
+    }
+    if !recipient_accepted(stream, recipient) {
+        return Err(Error::NotAccepted);
+    }
 
-        let stream = Stream {
-            id: stream_id,
-            sender: sender.clone(),
-            recipients: recipients.clone(),
-            token_contract: token_contract.clone(),
-            recipient_rate_per_second,
-            deposit,
-            start_time,
-            recipient_last_withdraw,
-            recipient_total_withdrawn,
-            is_active: true,
-            title: normalized_title.clone(),
-            description: normalized_description.clone(),
-        };
-        env.storage()
-            .persistent()
-            .set(&DataKey::StreamKey(stream_id), &stream);
-        next_id += 1;
-        env.storage()
-            .persistent()
-            .set(&DataKey::NextStreamId, &next_id);
+    if !cliff_reached(stream, ledger_now) {
+        return Ok(0i128);
+    }
 
-        // Update user stream indexes
-        // Add to sender's sent streams
-        let sender_clone = sender.clone();
-        let mut sent_streams: Vec<u32> = env
-            .storage()
-            .persistent()
-            .get(&DataKey::UserSentStreams(sender_clone.clone()))
-            .unwrap_or_else(|| Vec::new(&env));
-        sent_streams.push_back(stream_id);
-        env.storage()
-            .persistent()
-            .set(&DataKey::UserSentStreams(sender_clone), &sent_streams);
+    // Accrual is capped by this recipient's own allocation, so it doesn't depend on
+    // when (or whether) the other recipients withdraw
+    let now: u64 = accrual_time(stream, ledger_now);
+    let accrued = recipient_accrued(stream, recipient, now)?;
 
-        // Add to each recipient's received streams
-        for i in 0..recipients.len() {
-            let recipient = recipients.get(i).unwrap();
-            let recipient_clone = recipient.clone();
-            let mut received_streams: Vec<u32> = env
-                .storage()
-                .persistent()
-                .get(&DataKey::UserReceivedStreams(recipient_clone.clone()))
-                .unwrap_or_else(|| Vec::new(&env));
-            received_streams.push_back(stream_id);
-            env.storage().persistent().set(
-                &DataKey::UserReceivedStreams(recipient_clone),
-                &received_streams,
-            );
+    // The sender's lifetime cap on this recipient binds regardless of accrual
+    match stream.recipient_max_withdraw.get(recipient.clone()) {
+        Some(cap) => {
+            let withdrawn = stream
+                .recipient_total_withdrawn
+                .get(recipient.clone())
+                .unwrap_or(0i128);
+            Ok(core::cmp::min(
+                accrued,
+                core::cmp::max(cap - withdrawn, 0i128),
+            ))
         }
+        None => Ok(accrued),
+    }
+}
+
+/// Who a withdrawal in `withdraw_from_stream` runs on behalf of
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum WithdrawCaller {
+    /// The recipient authorized it: `min_withdraw_interval` is enforced and restarted
+    Recipient,
+    /// Anyone may trigger it (batch sweeps): the interval is neither checked nor restarted
+    Anyone,
+    /// A settlement the sender or admin triggers (removal, re-rating, rotation): as `Anyone`,
+    /// and no auto-refill either
+    Settlement,
+}
+
+/// Shared withdrawal path: settles `recipient`'s accrual and transfers it to `destination`.
+/// All per-recipient bookkeeping stays keyed on `recipient`. `amount` limits the payout to
+/// part of what has accrued; `None` withdraws everything. Fails with `TokenFrozen` while the
+/// token is frozen, settlements included. A `WithdrawCaller::Recipient` withdrawal also fails
+/// with `NotDueYet` when it comes sooner than the stream's `min_withdraw_interval` after the
+/// recipient's last one.
+fn withdraw_from_stream(
+    env: &Env,
+    stream_id: u32,
+    recipient: &Address,
+    destination: &Address,
+    amount: Option<i128>,
+    caller: WithdrawCaller,
+) -> Result<i128, Error> {
+    // fetch stream
+    let mut stream: Stream = env
+        .storage()
+        .persistent()
+        .get(&DataKey::StreamKey(stream_id))
+        .ok_or(Error::StreamNotFound)?;
+
+    if is_token_frozen(env, &stream.token_contract) {
+        return Err(Error::TokenFrozen);
+    }
+    let ledger_now = env.ledger().timestamp();
+    let withdrawable = stream_withdrawable(&stream, recipient, ledger_now)?;
+    let now: u64 = accrual_time(&stream, ledger_now);
+
+    let interval_applies =
+        caller == WithdrawCaller::Recipient && stream.min_withdraw_interval.is_some();
+    let too_soon = stream
+        .min_withdraw_interval
+        .zip(stream.recipient_last_withdrawn_at.get(recipient.clone()))
+        .is_some_and(|(min_interval, last)| ledger_now < last.saturating_add(min_interval));
+    if interval_applies && too_soon {
+        return Err(Error::NotDueYet);
+    }
+
+    // Bail out before touching the token so a zero payout costs no transfer
+    if withdrawable <= 0 {
+        return Err(Error::NothingToWithdraw);
+    }
+
+    let transfer_amount = match amount {
+        Some(a) if a <= 0 || a > withdrawable => return Err(Error::InvalidParameters),
+        Some(a) => a,
+        None => withdrawable,
+    };
+
+    // Move the last-withdraw baseline forward only by the time it took to accrue what was
+    // paid: the unpaid accrual is converted back into whole seconds (stepping back from `now`)
+    // plus a sub-unit carry, so it stays claimable later. Once the allocation caps a full
+    // payout the recipient is fully paid, so nothing carries over.
+    let numerator = recipient_accrual_numerator(&stream, recipient, now)?;
+    let period = stream.period_seconds as i128;
+    let (new_last_withdraw, carry) =
+        if transfer_amount == withdrawable && numerator / period > transfer_amount {
+            (now, 0i128)
+        } else {
+            let leftover = transfer_amount
+                .checked_mul(period)
+                .and_then(|paid| numerator.checked_sub(paid))
+                .ok_or(Error::ArithmeticOverflow)?;
+            let amt = stream
+                .recipient_amount_per_period
+                .get(recipient.clone())
+                .unwrap_or(0i128);
+            let last_withdraw = stream
+                .recipient_last_withdraw
+                .get(recipient.clone())
+                .unwrap_or(stream.start_time);
+            let step_back = if amt > 0 {
+                core::cmp::min((leftover / amt) as u64, now - last_withdraw)
+            } else {
+                0u64
+            };
+            (now - step_back, leftover - (step_back as i128) * amt)
+        };
+    stream
+        .recipient_accrual_remainder
+        .set(recipient.clone(), carry);
+
+    // Update this recipient's last withdrawal time
+    stream
+        .recipient_last_withdraw
+        .set(recipient.clone(), new_last_withdraw);
+    if interval_applies {
+        stream
+            .recipient_last_withdrawn_at
+            .set(recipient.clone(), ledger_now);
+    }
+
+    // Update this recipient's total withdrawn
+    let current_total = stream
+        .recipient_total_withdrawn
+        .get(recipient.clone())
+        .unwrap_or(0i128);
+    let new_total = current_total.saturating_add(transfer_amount);
+    stream
+        .recipient_total_withdrawn
+        .set(recipient.clone(), new_total);
+
+    // The deposit is exhausted once every recipient has withdrawn their full allocation
+    let mut outstanding: i128 = 0i128;
+    let mut total_distributed: i128 = 0i128;
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        outstanding = outstanding.saturating_add(recipient_unwithdrawn(&stream, &r));
+        total_distributed = total_distributed
+            .checked_add(stream.recipient_total_withdrawn.get(r).unwrap_or(0i128))
+            .ok_or(Error::ArithmeticOverflow)?;
+    }
+    let exhausted = outstanding <= 0;
+    if exhausted {
+        set_stream_active(env, &mut stream, false);
+    }
+
+    env.storage()
+        .persistent()
+        .set(&DataKey::StreamKey(stream_id), &stream);
+    extend_persistent(env, &DataKey::StreamKey(stream_id));
+    extend_persistent(env, &DataKey::UserReceivedStreams(recipient.clone()));
+
+    // TOKEN TRANSFER: contract -> destination. Done only after the updated accounting is
+    // stored, so a token that calls back into this contract sees the withdrawal already
+    // applied and can't claim the same accrual twice.
+    let token = TokenClient::new(env, &stream.token_contract);
+    let contract_addr = env.current_contract_address();
+
+    token.transfer(&contract_addr, destination, &transfer_amount);
+
+    // Carry the recipient's post-withdrawal cumulative so indexers can pick it up from any
+    // single event instead of summing the history
+    env.events().publish(
+        (symbol_short!("strm_wd"), stream_id),
+        (
+            recipient.clone(),
+            transfer_amount,
+            now,
+            destination.clone(),
+            new_total,
+        ),
+    );
+    record_stream_activity(env, stream_id, recipient, transfer_amount, ledger_now);
+
+    // Top up a low stream only now that the withdrawal is stored and paid out
+    if caller != WithdrawCaller::Settlement {
+        try_refill_stream(env, stream_id, &mut stream, ledger_now);
+    }
+
+    // Recipients that opted in (typically contracts such as vaults) get
+    // `on_stream_received(stream_id, amount)` on the address that received the funds. A
+    // failing or missing callback is ignored so it can't block the withdrawal.
+    if stream
+        .recipient_notify
+        .get(recipient.clone())
+        .unwrap_or(false)
+    {
+        let args: Vec<Val> = (stream_id, transfer_amount).into_val(env);
+        let _ = env.try_invoke_contract::<Val, soroban_sdk::Error>(
+            destination,
+            &Symbol::new(env, "on_stream_received"),
+            args,
+        );
+    }
+    // Only the withdrawal that flips the stream inactive gets here; later ones fail above
+    if exhausted {
+        env.events().publish(
+            (symbol_short!("strm_done"), stream_id),
+            (total_distributed, now),
+        );
+    }
+
+    Ok(transfer_amount)
+}
+
+/// Validate, store and index a new subscription and emit `sub_crt`. Auth is the caller's
+/// job, so batch entry points can authorize the subscriber once.
+fn register_subscription(
+    env: &Env,
+    subscriber: Address,
+    receiver: Address,
+    token_contract: Address,
+    amount_per_interval: i128,
+    interval_seconds: u64,
+    first_payment_time: u64,
+    title: Option<String>,
+    description: Option<String>,
+    grace_seconds: u64,
+    reference: Option<u64>,
+) -> u32 {
+    if amount_per_interval <= 0 || interval_seconds == 0 {
+        panic_with_error!(env, Error::InvalidParameters);
+    }
+    if !is_token_allowed(env, &token_contract) {
+        panic_with_error!(env, Error::TokenNotAllowed);
+    }
+    if is_token_frozen(env, &token_contract) {
+        panic_with_error!(env, Error::TokenFrozen);
+    }
+
+    let mut next_id: u32 = env
+        .storage()
+        .persistent()
+        .get(&DataKey::NextSubscriptionId)
+        .unwrap_or(1u32);
+    let sub_id = next_id;
+
+    let normalized_title =
+        normalize_optional_text(title, MAX_TITLE_LEN).unwrap_or_else(|e| panic_with_error!(env, e));
+    let normalized_description = normalize_optional_text(description, MAX_DESCRIPTION_LEN)
+        .unwrap_or_else(|e| panic_with_error!(env, e));
+
+    let subscription = Subscription {
+        id: sub_id,
+        subscriber: subscriber.clone(),
+        receiver: receiver.clone(),
+        token_contract: token_contract.clone(),
+        amount_per_interval,
+        interval_seconds,
+        next_payment_time: first_payment_time,
+        active: true,
+        balance: 0i128, // Start with zero balance - subscriber must deposit
+        title: normalized_title.clone(),
+        description: normalized_description.clone(),
+        grace_seconds,
+        paused_at: None,
+        max_catchup_intervals: None,
+        // The first payment anchors the cadence grid
+        billing_anchor: Some(first_payment_time),
+        prorated_first_amount: None,
+        reference,
+        cancelled_at: None,
+        total_charged: 0i128,
+        charge_count: 0u32,
+        keeper_fee: 0i128,
+        max_charges: None,
+    };
+
+    env.storage()
+        .persistent()
+        .set(&DataKey::SubscriptionKey(sub_id), &subscription);
+    adjust_active_count(env, &DataKey::ActiveSubscriptions, true);
+    next_id += 1;
+    env.storage()
+        .persistent()
+        .set(&DataKey::NextSubscriptionId, &next_id);
+
+    // Update user subscription indexes
+    // Add to subscriber's subscriptions
+    let subscriber_clone = subscriber.clone();
+    let mut subscriber_subs: Vec<u32> = env
+        .storage()
+        .persistent()
+        .get(&DataKey::UserSubscriptions(subscriber_clone.clone()))
+        .unwrap_or_else(|| Vec::new(env));
+    subscriber_subs.push_back(sub_id);
+    env.storage().persistent().set(
+        &DataKey::UserSubscriptions(subscriber_clone),
+        &subscriber_subs,
+    );
+
+    // Add to receiver's received subscriptions
+    let receiver_clone = receiver.clone();
+    let mut receiver_subs: Vec<u32> = env
+        .storage()
+        .persistent()
+        .get(&DataKey::UserReceivedSubscriptions(receiver_clone.clone()))
+        .unwrap_or_else(|| Vec::new(env));
+    receiver_subs.push_back(sub_id);
+    env.storage().persistent().set(
+        &DataKey::UserReceivedSubscriptions(receiver_clone),
+        &receiver_subs,
+    );
+
+    extend_persistent(env, &DataKey::SubscriptionKey(sub_id));
+    extend_persistent(env, &DataKey::UserSubscriptions(subscriber.clone()));
+    extend_persistent(env, &DataKey::UserReceivedSubscriptions(receiver.clone()));
+    extend_globals(env);
+
+    env.events().publish(
+        (symbol_short!("sub_crt"), sub_id),
+        (
+            subscriber,
+            receiver,
+            amount_per_interval,
+            interval_seconds,
+            first_payment_time,
+            normalized_title,
+            normalized_description,
+            reference,
+        ),
+    );
+
+    sub_id
+}
+
+/// Append a withdrawal to the stream's recent-activity buffer, dropping the oldest entry
+/// once it holds `MAX_STREAM_ACTIVITY`.
+fn record_stream_activity(
+    env: &Env,
+    stream_id: u32,
+    recipient: &Address,
+    amount: i128,
+    timestamp: u64,
+) {
+    let key = DataKey::StreamActivity(stream_id);
+    let mut activity: Vec<ActivitySummary> = env
+        .storage()
+        .persistent()
+        .get(&key)
+        .unwrap_or_else(|| Vec::new(env));
+    activity.push_back(ActivitySummary {
+        recipient: recipient.clone(),
+        amount,
+        timestamp,
+    });
+    while activity.len() > MAX_STREAM_ACTIVITY {
+        activity.pop_front();
+    }
+    env.storage().persistent().set(&key, &activity);
+    extend_persistent(env, &key);
+}
+
+/// Move `amount` from `payer` into the subscription's isolated escrow balance.
+/// Callers handle auth and validation.
+fn escrow_subscription_deposit(env: &Env, sub: &mut Subscription, payer: &Address, amount: i128) {
+    if is_token_frozen(env, &sub.token_contract) {
+        panic_with_error!(env, Error::TokenFrozen);
+    }
+
+    // Transfer tokens from payer to contract
+    let token = TokenClient::new(env, &sub.token_contract);
+    let contract_addr = env.current_contract_address();
+    token.transfer(payer, &contract_addr, &amount);
+
+    // Update subscription balance (isolated)
+    sub.balance = sub.balance.saturating_add(amount);
+    env.storage()
+        .persistent()
+        .set(&DataKey::SubscriptionKey(sub.id), &*sub);
+    extend_persistent(env, &DataKey::SubscriptionKey(sub.id));
+    extend_persistent(env, &DataKey::UserSubscriptions(sub.subscriber.clone()));
+
+    env.events().publish(
+        (symbol_short!("sub_dep"), sub.id),
+        (sub.subscriber.clone(), amount, sub.balance, payer.clone()),
+    );
+}
+
+/// Number of intervals due at `now`, counting any backlog (0 before `next_payment_time`).
+/// Nothing is owed past the last of `max_charges`. Every edit path keeps `interval_seconds`
+/// non-zero; a zero read from storage is still treated as one second rather than trapping.
+fn subscription_due_intervals(sub: &Subscription, now: u64) -> u64 {
+    if now < sub.next_payment_time {
+        return 0u64;
+    }
+    let interval = core::cmp::max(sub.interval_seconds, 1);
+    let due = (now - sub.next_payment_time) / interval + 1;
+    match sub.max_charges {
+        Some(max) => core::cmp::min(due, max.saturating_sub(sub.charge_count) as u64),
+        None => due,
+    }
+}
+
+/// Backlog owed at `now`, counted as `charge_subscription` does before any
+/// `max_catchup_intervals` cap: (intervals_due, amount_due).
+/// Paused or inactive subscriptions owe nothing.
+fn subscription_backlog(sub: &Subscription, now: u64) -> Result<(u64, i128), Error> {
+    if !sub.active || sub.paused_at.is_some() {
+        return Ok((0u64, 0i128));
+    }
+    let intervals_due = subscription_due_intervals(sub, now);
+    Ok((intervals_due, subscription_charge_cost(sub, intervals_due)?))
+}
+
+/// First point of the cadence grid `anchor + k * interval` (k may be negative) at or after `t`.
+fn next_on_grid(anchor: u64, interval: u64, t: u64) -> u64 {
+    if t <= anchor {
+        anchor - (anchor - t) / interval * interval
+    } else {
+        anchor + (t - anchor).div_ceil(interval) * interval
+    }
+}
+
+/// Cost of charging the next `intervals` intervals. Until it has been charged, the first
+/// interval of a pro-rated anchored subscription costs `prorated_first_amount`.
+/// A backlog too large to price is reported as `Error::ArithmeticOverflow` rather than
+/// clamped, so nobody is silently under-billed.
+fn subscription_charge_cost(sub: &Subscription, intervals: u64) -> Result<i128, Error> {
+    if intervals == 0 {
+        return Ok(0i128);
+    }
+    let first = sub.prorated_first_amount.unwrap_or(sub.amount_per_interval);
+    sub.amount_per_interval
+        .checked_mul((intervals - 1) as i128)
+        .and_then(|rest| rest.checked_add(first))
+        .ok_or(Error::ArithmeticOverflow)
+}
+
+/// Whether `charge_subscription` would collect at least one interval at `now`: the
+/// subscription is active, unpaused, due, funded for the next interval, and its token is not
+/// frozen.
+fn subscription_chargeable(env: &Env, sub: &Subscription, now: u64) -> bool {
+    sub.active
+        && sub.paused_at.is_none()
+        && now >= sub.next_payment_time
+        && matches!(subscription_charge_cost(sub, 1), Ok(cost) if sub.balance >= cost)
+        && !is_token_frozen(env, &sub.token_contract)
+}
+
+/// Shared charging path behind `charge_subscription`; see its docs for the semantics.
+/// Returns (intervals_charged, paid_to_receiver), the latter net of any platform fee.
+fn charge_due_subscription(
+    env: &Env,
+    subscription_id: u32,
+    keeper: Option<Address>,
+) -> Result<(u64, i128), Error> {
+    let mut sub: Subscription = env
+        .storage()
+        .persistent()
+        .get(&DataKey::SubscriptionKey(subscription_id))
+        .ok_or(Error::SubscriptionNotFound)?;
+
+    // Paused subscriptions are treated as inactive and keep their balance untouched
+    if !sub.active || sub.paused_at.is_some() {
+        return Err(Error::SubscriptionInactive);
+    }
+    if is_token_frozen(env, &sub.token_contract) {
+        return Err(Error::TokenFrozen);
+    }
+
+    let now: u64 = env.ledger().timestamp();
+    if now < sub.next_payment_time {
+        return Err(Error::NotDueYet);
+    }
+
+    // A subscriber-set catch-up cap bounds how much of a long backlog one charge settles;
+    // the rest stays due for later charges
+    let mut due_intervals = subscription_due_intervals(&sub, now);
+    if let Some(max) = sub.max_catchup_intervals {
+        due_intervals = core::cmp::min(due_intervals, max as u64);
+    }
+    // Caps can leave nothing owed even past `next_payment_time`; bail out before the
+    // affordability maths below assumes at least one interval
+    if due_intervals == 0 {
+        return Err(Error::NotDueYet);
+    }
+
+    // A keeper reward is only owed when there's a keeper to pay, and it is reserved out of
+    // the balance before any interval is priced
+    let keeper_fee = if keeper.is_some() { sub.keeper_fee } else { 0 };
+    let available = sub.balance.saturating_sub(keeper_fee);
+
+    // Charge only the whole intervals the subscription balance (isolated per subscription)
+    // covers; a pending pro-rated first interval is billed at its reduced price
+    let first_price = subscription_charge_cost(&sub, 1)?;
+    let charged_intervals = if available < first_price {
+        0u64
+    } else {
+        let affordable_rest = ((available - first_price) / sub.amount_per_interval) as u64;
+        1 + core::cmp::min(due_intervals - 1, affordable_rest)
+    };
+
+    // total amount to transfer
+    let amount_to_transfer = subscription_charge_cost(&sub, charged_intervals)?;
+    let keeper_fee = if charged_intervals > 0 { keeper_fee } else { 0 };
+
+    if charged_intervals > 0 {
+        // Deduct from subscription balance (isolated)
+        sub.balance = sub
+            .balance
+            .saturating_sub(amount_to_transfer)
+            .saturating_sub(keeper_fee);
+
+        // update next payment time; a schedule running past the end of time is an error,
+        // not a wrapped (or stuck) timestamp
+        sub.next_payment_time = charged_intervals
+            .checked_mul(sub.interval_seconds)
+            .and_then(|advance| sub.next_payment_time.checked_add(advance))
+            .ok_or(Error::ArithmeticOverflow)?;
+        sub.prorated_first_amount = None;
+
+        sub.total_charged = sub.total_charged.saturating_add(amount_to_transfer);
+        sub.charge_count = sub
+            .charge_count
+            .saturating_add(core::cmp::min(charged_intervals, u32::MAX as u64) as u32);
+    }
+
+    // Still underfunded past the grace window: stop retrying and let the subscription lapse
+    let lapsed = charged_intervals < due_intervals
+        && now > sub.next_payment_time.saturating_add(sub.grace_seconds);
+    if lapsed {
+        set_subscription_active(env, &mut sub, false);
+    }
+
+    // The last of `max_charges` went through: the subscription is complete and whatever is
+    // left in escrow goes back to the subscriber
+    let completed = sub.max_charges.is_some_and(|max| sub.charge_count >= max);
+    let mut completion_refund: i128 = 0i128;
+    if completed {
+        set_subscription_active(env, &mut sub, false);
+        completion_refund = sub.balance;
+        sub.balance = 0;
+    }
+
+    // Platform fee (if configured) comes out of the receiver's payout; the escrow is
+    // debited the full amount either way. Priced before anything is stored, so an
+    // overflow leaves the subscription untouched.
+    let fee_collector: Option<Address> = env.storage().persistent().get(&DataKey::FeeCollector);
+    let fee: i128 = if fee_collector.is_some() {
+        let fee_bps: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubFeeBps)
+            .unwrap_or(0u32);
+        amount_to_transfer
+            .checked_mul(fee_bps as i128)
+            .ok_or(Error::ArithmeticOverflow)?
+            / BPS_DENOMINATOR
+    } else {
+        0i128
+    };
+
+    env.storage()
+        .persistent()
+        .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+    extend_persistent(env, &DataKey::SubscriptionKey(subscription_id));
+    extend_persistent(env, &DataKey::UserSubscriptions(sub.subscriber.clone()));
+    extend_persistent(
+        env,
+        &DataKey::UserReceivedSubscriptions(sub.receiver.clone()),
+    );
+    extend_globals(env);
+
+    let mut paid_to_receiver: i128 = 0i128;
+    if amount_to_transfer > 0 {
+        // Transfer from contract to receiver, after the new balance is stored (see
+        // `withdraw_from_stream`)
+        let token = TokenClient::new(env, &sub.token_contract);
+        let contract_addr = env.current_contract_address();
+
+        if let Some(collector) = fee_collector.filter(|_| fee > 0) {
+            token.transfer(&contract_addr, &collector, &fee);
+            record_fee(env, &sub.token_contract, fee);
+        }
+
+        paid_to_receiver = amount_to_transfer - fee;
+        token.transfer(&contract_addr, &sub.receiver, &paid_to_receiver);
+
+        if let Some(keeper) = keeper.filter(|_| keeper_fee > 0) {
+            token.transfer(&contract_addr, &keeper, &keeper_fee);
+            env.events().publish(
+                (symbol_short!("sub_kpr"), subscription_id),
+                (keeper, keeper_fee),
+            );
+        }
+
+        env.events().publish(
+            (symbol_short!("sub_chrg"), subscription_id),
+            (
+                sub.receiver.clone(),
+                amount_to_transfer,
+                sub.next_payment_time,
+                fee,
+            ),
+        );
+    }
+
+    // This charge used up the last of the escrow: prompt a top-up before the next interval
+    if amount_to_transfer > 0 && sub.balance == 0 && sub.active {
+        env.events().publish(
+            (symbol_short!("sub_empty"), subscription_id),
+            (sub.subscriber.clone(), sub.next_payment_time),
+        );
+    }
+
+    if charged_intervals < due_intervals {
+        env.events().publish(
+            (symbol_short!("sub_short"), subscription_id),
+            (
+                sub.subscriber.clone(),
+                due_intervals - charged_intervals,
+                sub.balance,
+                now,
+            ),
+        );
+    }
+
+    if completed {
+        if completion_refund > 0 {
+            let token = TokenClient::new(env, &sub.token_contract);
+            token.transfer(
+                &env.current_contract_address(),
+                &sub.subscriber,
+                &completion_refund,
+            );
+        }
+        env.events().publish(
+            (symbol_short!("sub_done"), subscription_id),
+            (
+                sub.subscriber.clone(),
+                sub.charge_count,
+                completion_refund,
+                now,
+            ),
+        );
+    }
+
+    if lapsed {
+        env.events().publish(
+            (symbol_short!("sub_lapse"), subscription_id),
+            (
+                sub.subscriber.clone(),
+                sub.receiver.clone(),
+                sub.balance,
+                now,
+            ),
+        );
+    }
+
+    Ok((charged_intervals, paid_to_receiver))
+}
+
+/// Load a subscription for a terms change, authorising both parties and first charging
+/// any interval that is already due so it settles at the old terms. Fails with
+/// `InsufficientContractBalance` (leaving the partial charge undone) when that charge can't
+/// settle the whole backlog, so unpaid intervals are never re-priced under the new terms.
+fn settle_for_terms_change(env: &Env, subscription_id: u32) -> Result<Subscription, Error> {
+    let sub: Subscription = env
+        .storage()
+        .persistent()
+        .get(&DataKey::SubscriptionKey(subscription_id))
+        .ok_or(Error::SubscriptionNotFound)?;
+
+    sub.subscriber.require_auth();
+    sub.receiver.require_auth();
+
+    if !sub.active {
+        return Err(Error::SubscriptionInactive);
+    }
+
+    let now: u64 = env.ledger().timestamp();
+    if sub.paused_at.is_none() && now >= sub.next_payment_time {
+        charge_due_subscription(env, subscription_id, None)?;
+        let settled: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
+        if !settled.active {
+            return Err(Error::SubscriptionInactive);
+        }
+        if now >= settled.next_payment_time {
+            return Err(Error::InsufficientContractBalance);
+        }
+        return Ok(settled);
+    }
+
+    Ok(sub)
+}
+
+/// Close a stream: pay every recipient what they can withdraw right now (their vested,
+/// unwithdrawn accrual), refund everything else to the sender, and mark it inactive.
+/// Before the cliff nothing has vested, so the whole unwithdrawn deposit goes back, and
+/// under `CancelPolicy::RefundSender` recipients get nothing either. Fails with
+/// `TokenFrozen`, before touching anything, while the token is frozen.
+/// Returns (refunded, settled_to_recipients, accrual_time).
+fn settle_cancelled_stream(
+    env: &Env,
+    stream_id: u32,
+    stream: &mut Stream,
+    policy: CancelPolicy,
+) -> Result<(i128, i128, u64), Error> {
+    if is_token_frozen(env, &stream.token_contract) {
+        return Err(Error::TokenFrozen);
+    }
+    let ledger_now = env.ledger().timestamp();
+    let now: u64 = accrual_time(stream, ledger_now);
+
+    let mut unwithdrawn: i128 = 0i128;
+    let mut settled: i128 = 0i128;
+    let mut payouts: Vec<(Address, i128)> = Vec::new(env);
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        unwithdrawn = unwithdrawn.saturating_add(recipient_unwithdrawn(stream, &r));
+        let owed = match policy {
+            CancelPolicy::RefundSender => 0i128,
+            CancelPolicy::SettleRecipients => match stream_withdrawable(stream, &r, ledger_now) {
+                Ok(amount) => amount,
+                Err(Error::NotAccepted) => 0i128,
+                Err(e) => return Err(e),
+            },
+        };
+        if owed > 0 {
+            settled = settled.checked_add(owed).ok_or(Error::ArithmeticOverflow)?;
+            payouts.push_back((r, owed));
+        }
+    }
+    for (r, owed) in payouts.iter() {
+        let total = stream
+            .recipient_total_withdrawn
+            .get(r.clone())
+            .unwrap_or(0i128);
+        stream
+            .recipient_total_withdrawn
+            .set(r.clone(), total.saturating_add(owed));
+        stream.recipient_last_withdraw.set(r.clone(), now);
+        stream.recipient_accrual_remainder.set(r, 0i128);
+    }
+    let refund = unwithdrawn - settled;
+
+    // mark inactive
+    set_stream_active(env, stream, false);
+    stream.deposit = 0;
+    env.storage()
+        .persistent()
+        .set(&DataKey::StreamKey(stream_id), &*stream);
+
+    // Pay out, once the stream is stored as closed
+    let token = TokenClient::new(env, &stream.token_contract);
+    let contract_addr = env.current_contract_address();
+    for (r, owed) in payouts.iter() {
+        token.transfer(&contract_addr, &r, &owed);
+    }
+    if refund > 0 {
+        token.transfer(&contract_addr, &stream.sender, &refund);
+    }
+
+    Ok((refund, settled, now))
+}
+
+/// Take `recipient` out of a stream, shared by `remove_recipient` and `recipient_close`:
+/// their outstanding accrual is paid out, the unaccrued rest of their allocation is re-split
+/// across the remaining recipients, and removing the last one closes the stream with that
+/// rest refunded to the sender. Callers handle auth and the event.
+/// Returns (settled_to_recipient, refunded_to_sender, accrual_time).
+fn detach_recipient(
+    env: &Env,
+    stream_id: u32,
+    recipient: &Address,
+) -> Result<(i128, i128, u64), Error> {
+    let stream: Stream = env
+        .storage()
+        .persistent()
+        .get(&DataKey::StreamKey(stream_id))
+        .ok_or(Error::StreamNotFound)?;
+
+    if !stream.is_active {
+        return Err(Error::StreamInactive);
+    }
+    if stream.recipients.first_index_of(recipient).is_none() {
+        return Err(Error::NotARecipient);
+    }
+
+    // Settle what the recipient has accrued so far
+    let settled = match withdraw_from_stream(
+        env,
+        stream_id,
+        recipient,
+        recipient,
+        None,
+        WithdrawCaller::Settlement,
+    ) {
+        Ok(amount) => amount,
+        Err(Error::NothingToWithdraw) => 0i128,
+        Err(e) => return Err(e),
+    };
+
+    let mut stream: Stream = env
+        .storage()
+        .persistent()
+        .get(&DataKey::StreamKey(stream_id))
+        .ok_or(Error::StreamNotFound)?;
+    let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+    // Accrual that hasn't vested (before the cliff) is forfeited along with the rest
+    let freed = if cliff_reached(&stream, env.ledger().timestamp()) {
+        recipient_unwithdrawn(&stream, recipient) - recipient_accrued(&stream, recipient, now)?
+    } else {
+        recipient_unwithdrawn(&stream, recipient)
+    };
+
+    let index = stream.recipients.first_index_of(recipient).unwrap();
+    stream.recipients.remove(index);
+    stream.recipient_rate_per_second.remove(recipient.clone());
+    stream.recipient_amount_per_period.remove(recipient.clone());
+    stream.recipient_accrual_remainder.remove(recipient.clone());
+    stream.recipient_last_withdraw.remove(recipient.clone());
+    stream.recipient_total_withdrawn.remove(recipient.clone());
+    stream.recipient_allocation.remove(recipient.clone());
+    stream.weights.remove(recipient.clone());
+    stream.accepted.remove(recipient.clone());
+    stream.recipient_max_withdraw.remove(recipient.clone());
+    stream.recipient_notify.remove(recipient.clone());
+    stream.recipient_last_withdrawn_at.remove(recipient.clone());
+
+    let mut refund: i128 = 0i128;
+    if stream.recipients.is_empty() {
+        // Nobody left to stream to: hand the unaccrued remainder back to the sender
+        refund = freed;
+        set_stream_active(env, &mut stream, false);
+        stream.deposit = 0;
+    } else if stream.is_active {
+        rebalance_allocations(&mut stream, now)?;
+        allocate_by_rate(&mut stream, freed)?;
+    }
+
+    env.storage()
+        .persistent()
+        .set(&DataKey::StreamKey(stream_id), &stream);
+    extend_persistent(env, &DataKey::StreamKey(stream_id));
+    remove_from_index(
+        env,
+        &DataKey::UserReceivedStreams(recipient.clone()),
+        stream_id,
+    );
+
+    // Refund only once the closed stream is stored (see `withdraw_from_stream`)
+    if refund > 0 {
+        let token = TokenClient::new(env, &stream.token_contract);
+        let contract_addr = env.current_contract_address();
+        token.transfer(&contract_addr, &stream.sender, &refund);
+    }
+
+    Ok((settled, refund, now))
+}
+
+/// Cancellation path shared by `cancel_stream` and `cancel_all_sent_streams`; see
+/// `cancel_stream` for the semantics. Returns the refund, or `None` if this call only gave
+/// notice.
+fn cancel_sent_stream(env: &Env, stream_id: u32) -> Result<Option<i128>, Error> {
+    let mut stream: Stream = env
+        .storage()
+        .persistent()
+        .get(&DataKey::StreamKey(stream_id))
+        .ok_or(Error::StreamNotFound)?;
+
+    if !stream.is_active {
+        return Err(Error::StreamInactive);
+    }
+
+    let ledger_now: u64 = env.ledger().timestamp();
+    let notice = stream.cancel_notice_seconds.unwrap_or(0u64);
+    match stream.cancel_requested_at {
+        Some(requested_at) => {
+            // Notice already given: finalizing is open to anyone once it has run out
+            if ledger_now < requested_at.saturating_add(notice) {
+                return Err(Error::NotDueYet);
+            }
+        }
+        None if notice > 0 => {
+            stream.sender.require_auth();
+
+            let effective_at = ledger_now.saturating_add(notice);
+            stream.cancel_requested_at = Some(ledger_now);
+            stream.end_time = Some(match stream.end_time {
+                Some(end_time) => core::cmp::min(end_time, effective_at),
+                None => effective_at,
+            });
+            env.storage()
+                .persistent()
+                .set(&DataKey::StreamKey(stream_id), &stream);
+            extend_persistent(env, &DataKey::StreamKey(stream_id));
+
+            env.events().publish(
+                (Symbol::new(env, "strm_cnotice"), stream_id),
+                (stream.sender.clone(), ledger_now, effective_at),
+            );
+            return Ok(None);
+        }
+        // only sender can cancel
+        None => stream.sender.require_auth(),
+    }
+
+    let policy = stream.cancel_policy;
+    let (remaining_deposit, settled, now) =
+        settle_cancelled_stream(env, stream_id, &mut stream, policy)?;
+
+    env.events().publish(
+        (symbol_short!("strm_can"), stream_id),
+        (stream.sender.clone(), remaining_deposit, settled, now),
+    );
+
+    Ok(Some(remaining_deposit))
+}
+
+/// Drop a stream from its sender's and recipients' ID indexes.
+fn unindex_stream(env: &Env, stream_id: u32, stream: &Stream) {
+    remove_from_index(
+        env,
+        &DataKey::UserSentStreams(stream.sender.clone()),
+        stream_id,
+    );
+    for i in 0..stream.recipients.len() {
+        let r = stream.recipients.get(i).unwrap();
+        remove_from_index(env, &DataKey::UserReceivedStreams(r), stream_id);
+    }
+}
+
+/// Add a platform fee just paid to the collector to the running total for `token`.
+fn record_fee(env: &Env, token: &Address, fee: i128) {
+    let key = DataKey::CollectedFees(token.clone());
+    let collected: i128 = env.storage().persistent().get(&key).unwrap_or(0i128);
+    env.storage()
+        .persistent()
+        .set(&key, &collected.saturating_add(fee));
+    extend_persistent(env, &key);
+
+    env.events()
+        .publish((symbol_short!("fee_take"), token.clone()), fee);
+}
+
+/// Address of the Stellar Asset Contract wrapping native XLM, derived from the native asset
+/// (its XDR is just the `ASSET_TYPE_NATIVE` discriminant), so it matches on every network.
+fn native_token_address(env: &Env) -> Address {
+    let native_asset = Bytes::from_array(env, &[0u8; 4]);
+    env.deployer()
+        .with_stellar_asset(native_asset)
+        .deployed_address()
+}
+
+/// Fail with `NotInitialized` until `init` has set the platform admin, so nothing can be
+/// created or moved on a contract that has no admin, fee or allow-list configuration yet.
+fn ensure_initialized(env: &Env) -> Result<(), Error> {
+    if env.storage().persistent().has(&DataKey::PlatformAdmin) {
+        Ok(())
+    } else {
+        Err(Error::NotInitialized)
+    }
+}
+
+/// Move the `ActiveStreams`/`ActiveSubscriptions` counter at `key` by one.
+fn adjust_active_count(env: &Env, key: &DataKey, increase: bool) {
+    let count: u32 = env.storage().persistent().get(key).unwrap_or(0u32);
+    let count = if increase {
+        count.saturating_add(1)
+    } else {
+        count.saturating_sub(1)
+    };
+    env.storage().persistent().set(key, &count);
+}
+
+/// Set `stream.is_active`, keeping the `ActiveStreams` counter in step. Only an actual
+/// change moves the counter, so closing an already-closed stream can't count twice.
+fn set_stream_active(env: &Env, stream: &mut Stream, active: bool) {
+    if stream.is_active != active {
+        adjust_active_count(env, &DataKey::ActiveStreams, active);
+        stream.is_active = active;
+    }
+}
+
+/// Set `sub.active`, keeping the `ActiveSubscriptions` counter in step (see
+/// `set_stream_active`).
+fn set_subscription_active(env: &Env, sub: &mut Subscription, active: bool) {
+    if sub.active != active {
+        adjust_active_count(env, &DataKey::ActiveSubscriptions, active);
+        sub.active = active;
+    }
+}
+
+/// Convert a stream stored in a legacy layout (see `StreamV1`/`StreamV0`) to the current
+/// one, with every later feature off. Version 0 streams accrued per second from a shared
+/// pool, so they become one-second periods and what is left of the pool is earmarked by
+/// rate. Returns `None` for any other layout, the current one included.
+fn stream_from_legacy(env: &Env, fields: &Map<Symbol, Val>) -> Option<Stream> {
+    let (v1, pooled) = match fields.len() {
+        STREAM_V1_FIELDS => (StreamV1::try_from_val(env, fields.as_val()).ok()?, 0i128),
+        STREAM_V0_FIELDS => {
+            let v0 = StreamV0::try_from_val(env, fields.as_val()).ok()?;
+            let mut withdrawn: i128 = 0i128;
+            for i in 0..v0.recipients.len() {
+                let r = v0.recipients.get(i).unwrap();
+                let amount = v0.recipient_total_withdrawn.get(r).unwrap_or(0i128);
+                withdrawn = withdrawn.checked_add(amount)?;
+            }
+            let pooled = core::cmp::max(v0.deposit.checked_sub(withdrawn)?, 0i128);
+            let v1 = StreamV1 {
+                id: v0.id,
+                sender: v0.sender,
+                recipients: v0.recipients,
+                token_contract: v0.token_contract,
+                recipient_amount_per_period: v0.recipient_rate_per_second.clone(),
+                recipient_rate_per_second: v0.recipient_rate_per_second,
+                period_seconds: 1u64,
+                recipient_accrual_remainder: Map::new(env),
+                deposit: v0.deposit,
+                start_time: v0.start_time,
+                recipient_last_withdraw: v0.recipient_last_withdraw,
+                recipient_allocation: v0.recipient_total_withdrawn.clone(),
+                recipient_total_withdrawn: v0.recipient_total_withdrawn,
+                is_active: v0.is_active,
+                title: v0.title,
+                description: v0.description,
+                paused_at: None,
+                end_time: None,
+                cliff_time: None,
+            };
+            (v1, pooled)
+        }
+        _ => return None,
+    };
+
+    let mut stream = Stream {
+        id: v1.id,
+        sender: v1.sender,
+        recipients: v1.recipients,
+        token_contract: v1.token_contract,
+        recipient_rate_per_second: v1.recipient_rate_per_second,
+        recipient_amount_per_period: v1.recipient_amount_per_period,
+        period_seconds: v1.period_seconds,
+        recipient_accrual_remainder: v1.recipient_accrual_remainder,
+        deposit: v1.deposit,
+        start_time: v1.start_time,
+        recipient_last_withdraw: v1.recipient_last_withdraw,
+        recipient_total_withdrawn: v1.recipient_total_withdrawn,
+        recipient_allocation: v1.recipient_allocation,
+        is_active: v1.is_active,
+        title: v1.title,
+        description: v1.description,
+        paused_at: v1.paused_at,
+        end_time: v1.end_time,
+        cliff_time: v1.cliff_time,
+        cancel_notice_seconds: None,
+        cancel_requested_at: None,
+        weights: Map::new(env),
+        require_acceptance: false,
+        accepted: Map::new(env),
+        recipient_max_withdraw: Map::new(env),
+        reference: None,
+        gated: false,
+        gate_approver: None,
+        recipient_notify: Map::new(env),
+        cancel_policy: CancelPolicy::SettleRecipients,
+        min_withdraw_interval: None,
+        recipient_last_withdrawn_at: Map::new(env),
+        refill_from: None,
+        refill_amount: 0i128,
+    };
+    if pooled > 0 {
+        allocate_by_rate(&mut stream, pooled).ok()?;
+    }
+    Some(stream)
+}
+
+/// Convert a subscription stored in a legacy layout (see `SubscriptionV1`/`SubscriptionV0`)
+/// to the current one, with every later feature off and its cadence grid anchored at the
+/// next payment. Returns `None` for any other layout, the current one included.
+fn subscription_from_legacy(env: &Env, fields: &Map<Symbol, Val>) -> Option<Subscription> {
+    let v1 = match fields.len() {
+        SUBSCRIPTION_V1_FIELDS => SubscriptionV1::try_from_val(env, fields.as_val()).ok()?,
+        SUBSCRIPTION_V0_FIELDS => {
+            let v0 = SubscriptionV0::try_from_val(env, fields.as_val()).ok()?;
+            SubscriptionV1 {
+                id: v0.id,
+                subscriber: v0.subscriber,
+                receiver: v0.receiver,
+                token_contract: v0.token_contract,
+                amount_per_interval: v0.amount_per_interval,
+                interval_seconds: v0.interval_seconds,
+                next_payment_time: v0.next_payment_time,
+                active: v0.active,
+                balance: v0.balance,
+                title: v0.title,
+                description: v0.description,
+                grace_seconds: 0u64,
+                paused_at: None,
+            }
+        }
+        _ => return None,
+    };
+
+    Some(Subscription {
+        id: v1.id,
+        subscriber: v1.subscriber,
+        receiver: v1.receiver,
+        token_contract: v1.token_contract,
+        amount_per_interval: v1.amount_per_interval,
+        interval_seconds: v1.interval_seconds,
+        next_payment_time: v1.next_payment_time,
+        active: v1.active,
+        balance: v1.balance,
+        title: v1.title,
+        description: v1.description,
+        grace_seconds: v1.grace_seconds,
+        paused_at: v1.paused_at,
+        max_catchup_intervals: None,
+        billing_anchor: Some(v1.next_payment_time),
+        prorated_first_amount: None,
+        reference: None,
+        cancelled_at: None,
+        total_charged: 0i128,
+        charge_count: 0u32,
+        keeper_fee: 0i128,
+        max_charges: None,
+    })
+}
+
+/// Rewrite stream `stream_id` in the current layout if it is stored in a legacy one. Legacy
+/// records predate the active counters, so an active one is counted as it is converted.
+fn migrate_stream_record(env: &Env, stream_id: u32) {
+    let key = DataKey::StreamKey(stream_id);
+    let fields: Map<Symbol, Val> = match env.storage().persistent().get(&key) {
+        Some(fields) => fields,
+        None => return,
+    };
+    if let Some(stream) = stream_from_legacy(env, &fields) {
+        if stream.is_active {
+            adjust_active_count(env, &DataKey::ActiveStreams, true);
+        }
+        env.storage().persistent().set(&key, &stream);
+        extend_persistent(env, &key);
+    }
+}
+
+/// Subscription counterpart of `migrate_stream_record`.
+fn migrate_subscription_record(env: &Env, subscription_id: u32) {
+    let key = DataKey::SubscriptionKey(subscription_id);
+    let fields: Map<Symbol, Val> = match env.storage().persistent().get(&key) {
+        Some(fields) => fields,
+        None => return,
+    };
+    if let Some(sub) = subscription_from_legacy(env, &fields) {
+        if sub.active {
+            adjust_active_count(env, &DataKey::ActiveSubscriptions, true);
+        }
+        env.storage().persistent().set(&key, &sub);
+        extend_persistent(env, &key);
+    }
+}
+
+/// Count a stream creation by `sender` against the per-ledger cap (`MaxStreamsPerLedger`,
+/// `DEFAULT_MAX_STREAMS_PER_LEDGER` if unset, 0 = no cap). This bounds how fast one sender
+/// can grow their own and their recipients' stream indexes.
+fn check_creation_rate(env: &Env, sender: &Address) -> Result<(), Error> {
+    let max: u32 = env
+        .storage()
+        .persistent()
+        .get(&DataKey::MaxStreamsPerLedger)
+        .unwrap_or(DEFAULT_MAX_STREAMS_PER_LEDGER);
+    if max == 0 {
+        return Ok(());
+    }
+
+    // Keyed by ledger sequence in temporary storage, so old counters simply expire
+    let key = DataKey::StreamsCreated(sender.clone(), env.ledger().sequence());
+    let created: u32 = env.storage().temporary().get(&key).unwrap_or(0u32);
+    if created >= max {
+        return Err(Error::RateLimited);
+    }
+    env.storage().temporary().set(&key, &(created + 1));
+    Ok(())
+}
+
+/// Whether `token` may back a new stream or subscription.
+/// An empty allow-list keeps creation permissionless.
+fn is_token_allowed(env: &Env, token: &Address) -> bool {
+    let allowed: Map<Address, bool> = env
+        .storage()
+        .persistent()
+        .get(&DataKey::AllowedTokens)
+        .unwrap_or(Map::new(env));
+    allowed.is_empty() || allowed.get(token.clone()).unwrap_or(false)
+}
+
+/// Whether the admin has frozen `token` (see `freeze_token`).
+fn is_token_frozen(env: &Env, token: &Address) -> bool {
+    env.storage()
+        .persistent()
+        .get(&DataKey::FrozenTokens(token.clone()))
+        .unwrap_or(false)
+}
+
+/// Error codes
+#[contracterror]
+#[derive(Clone, Copy, Debug, Eq, PartialEq, PartialOrd, Ord)]
+#[repr(u32)]
+pub enum Error {
+    AlreadyInitialized = 1,
+    InvalidParameters = 2,
+    ContractInsufficientBalance = 3,
+    StreamNotFound = 4,
+    StreamInactive = 5,
+    NothingToWithdraw = 6,
+    SubscriptionNotFound = 7,
+    SubscriptionInactive = 8,
+    NotDueYet = 9,
+    InsufficientContractBalance = 10,
+    NotInitialized = 11,
+    DuplicateRecipient = 12,
+    /// A recipient's rate or weighted share rounds to nothing per stream period. Only returned by
+    /// `add_recipient`, `update_recipient_rate` and `update_recipient_weight`; creation takes
+    /// amounts in the stream's own period, accrues them exactly and rejects non-positive
+    /// ones with `InvalidParameters`.
+    RateTooSmall = 13,
+    NotARecipient = 14,
+    StreamPaused = 15,
+    StreamNotPaused = 16,
+    NoPendingAdmin = 17,
+    SubscriptionPaused = 18,
+    SubscriptionNotPaused = 19,
+    TokenNotAllowed = 20,
+    ArithmeticOverflow = 21,
+    StreamStillActive = 22,
+    Unauthorized = 23,
+    NotAccepted = 24,
+    RateLimited = 25,
+    TokenFrozen = 26,
+}
+
+/// Data keys in storage
+#[contracttype]
+#[derive(Clone)]
+pub enum DataKey {
+    PlatformAdmin,
+    NextStreamId,
+    StreamKey(u32), // stream_id -> Stream
+    NextSubscriptionId,
+    SubscriptionKey(u32),               // subscription_id -> Subscription
+    TokenContract,                      // Address (optional global token id if you want a default)
+    UserSentStreams(Address), // user address -> Vec<u32> (stream IDs where user is sender)
+    UserReceivedStreams(Address), // user address -> Vec<u32> (stream IDs where user is recipient)
+    UserSubscriptions(Address), // user address -> Vec<u32> (subscription IDs where user is subscriber)
+    UserReceivedSubscriptions(Address), // user address -> Vec<u32> (subscription IDs where user is receiver)
+    StreamFeeBps,                       // u32 fee in basis points taken from stream deposits
+    FeeCollector,                       // Address receiving platform fees
+    PendingAdmin,                       // Address proposed as the next platform admin
+    AllowedTokens,                      // Map<Address, bool> of allowed tokens (empty = any)
+    Version,                            // u32 contract version recorded at init/migrate
+    MinDeposit,                         // i128 smallest stream deposit accepted (0 = no limit)
+    MinPeriodSeconds,                   // u64 shortest stream period accepted (0 = no limit)
+    StaleSeconds,                       // u64 uncharged seconds before escrow is reclaimable
+    SubFeeBps,                          // u32 fee in basis points taken from subscription charges
+    CollectedFees(Address),             // i128 platform fees collected so far in a token
+    MaxStreamsPerLedger,                // u32 per-sender stream creations allowed per ledger
+    StreamsCreated(Address, u32),       // Temporary: u32 streams a sender created in a ledger
+    ActiveStreams,                      // u32 streams currently active
+    ActiveSubscriptions,                // u32 subscriptions currently active
+    StreamActivity(u32), // stream_id -> Vec<ActivitySummary> of its latest withdrawals
+    FrozenTokens(Address), // bool set while the admin has frozen a compromised token
+    MigrationCursor,     // (u32, u32) next stream and subscription IDs `migrate` will check
+}
+
+/// A streaming payment: continuous rate-based escrow
+#[contracttype]
+#[derive(Clone)]
+pub struct Stream {
+    pub id: u32,
+    pub sender: Address,
+    pub recipients: Vec<Address>, // Multiple recipients (changed from single Address)
+    pub token_contract: Address,
+    // Per-recipient rate in atomic units per second, derived from amount-per-period / period_seconds.
+    // Truncated (0 for less than one unit a second) and kept for reads only; nothing accrues from it
+    pub recipient_rate_per_second: Map<Address, i128>,
+    pub recipient_amount_per_period: Map<Address, i128>, // Exact per-recipient amount per period (accrual source)
+    pub period_seconds: u64,
+    pub recipient_accrual_remainder: Map<Address, i128>, // Sub-unit accrual carried between withdrawals
+    pub deposit: i128, // total deposited, including top-ups (remaining is derived)
+    pub start_time: u64, // ledger timestamp seconds
+    pub recipient_last_withdraw: Map<Address, u64>, // Per-recipient last withdrawal time
+    pub recipient_total_withdrawn: Map<Address, i128>, // Per-recipient total withdrawn amount
+    pub recipient_allocation: Map<Address, i128>, // Per-recipient share of the deposit (withdrawn + outstanding)
+    pub is_active: bool,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub paused_at: Option<u64>,  // Set while the sender has paused accrual
+    pub end_time: Option<u64>,   // Nothing accrues past this timestamp (fixed-term streams)
+    pub cliff_time: Option<u64>, // Nothing is withdrawable before this timestamp (vesting)
+    pub cancel_notice_seconds: Option<u64>, // Notice a sender cancel must give before it takes effect
+    pub cancel_requested_at: Option<u64>,   // Set once the sender has given cancellation notice
+    pub weights: Map<Address, u32>, // Split-stream weights; weighted recipients share one total rate
+    pub require_acceptance: bool,   // Recipients must call accept_stream before they accrue
+    pub accepted: Map<Address, bool>, // Recipients who have accepted (acceptance mode only)
+    pub recipient_max_withdraw: Map<Address, i128>, // Optional lifetime withdrawal cap per recipient
+    pub reference: Option<u64>,                     // External invoice/reference ID set at creation
+    pub gated: bool, // Nothing accrues until the sender or gate approver calls release_gate
+    pub gate_approver: Option<Address>, // May release the gate besides the sender
+    pub recipient_notify: Map<Address, bool>, // Recipients called back on withdrawal
+    pub cancel_policy: CancelPolicy, // Who gets unwithdrawn accrual on cancel
+    pub min_withdraw_interval: Option<u64>, // Shortest gap between one recipient's withdrawals
+    pub recipient_last_withdrawn_at: Map<Address, u64>, // Ledger time of each one's last withdrawal
+    pub refill_from: Option<Address>, // Pulled from (via allowance) when the stream runs low
+    pub refill_amount: i128, // Amount pulled per refill, also the low-water mark
+}
+
+/// What cancelling a stream does with accrual recipients have not withdrawn yet
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum CancelPolicy {
+    SettleRecipients, // Pay recipients what they can withdraw; refund the rest
+    RefundSender,     // Refund everything not yet withdrawn to the sender
+}
+
+/// Optional schedule and cancellation terms for `create_stream`.
+#[contracttype]
+#[derive(Clone)]
+pub struct StreamOptions {
+    pub start_at: Option<u64>, // Future start timestamp; `None` starts the stream immediately
+    pub end_time: Option<u64>, // Caps accrual for fixed-term agreements; `None` runs until the deposit is used
+    pub cliff_time: Option<u64>, // Vesting cliff; accrual from `start_time` unlocks at once when reached
+    pub cancel_notice_seconds: Option<u64>, // Recipients keep accruing this long after a cancel request
+    pub require_acceptance: bool, // Start inactive until recipients opt in with accept_stream
+    pub reference: Option<u64>,   // External invoice/reference ID, echoed in `strm_crt`
+    pub gated: bool,              // Hold accrual until release_gate (milestone payments)
+    pub gate_approver: Option<Address>, // Who besides the sender may release the gate
+    pub cancel_policy: CancelPolicy, // Who gets unwithdrawn accrual when cancelled
+    pub min_withdraw_interval: Option<u64>, // Seconds a recipient must wait between withdrawals
+}
+
+/// A stream together with fields derived from it at the current ledger time
+#[contracttype]
+#[derive(Clone)]
+pub struct StreamView {
+    pub stream: Stream,
+    pub remaining_deposit: i128, // Deposit not yet accrued to any recipient
+    pub total_outflow_rate: i128, // Per-second outflow times RATE_SCALE while it flows, else 0
+    pub is_exhausted: bool,      // Inactive, ended, or fully accrued
+}
+
+/// Summary figures for one user, see `get_user_dashboard`
+#[contracttype]
+#[derive(Clone)]
+pub struct UserDashboard {
+    pub incoming_rate: i128, // Accrual across streams paying the user, per second times RATE_SCALE
+    pub outgoing_rate: i128, // Outflow across the user's own streams, per second times RATE_SCALE
+    pub active_sent_streams: u32,
+    pub active_received_streams: u32,
+    pub active_subscriptions: u32, // Active subscriptions the user pays for
+    pub subscriber_escrow: i128,   // Balance escrowed across the user's subscriptions
+    pub truncated: bool,           // Some index was longer than the part that was read
+}
+
+/// One withdrawal in a stream's recent-activity buffer
+#[contracttype]
+#[derive(Clone, Debug, Eq, PartialEq)]
+pub struct ActivitySummary {
+    pub recipient: Address,
+    pub amount: i128,
+    pub timestamp: u64, // Ledger time of the withdrawal
+}
+
+/// A recurring subscription (pull/payments at intervals)
+#[contracttype]
+#[derive(Clone)]
+pub struct Subscription {
+    pub id: u32,
+    pub subscriber: Address,
+    pub receiver: Address,
+    pub token_contract: Address,
+    pub amount_per_interval: i128,
+    pub interval_seconds: u64,
+    pub next_payment_time: u64,
+    pub active: bool,
+    pub balance: i128, // Escrowed balance for this subscription (isolated from other subscriptions)
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub grace_seconds: u64, // How long an underfunded payment may stay overdue before the subscription lapses
+    pub paused_at: Option<u64>, // Set while the subscriber has put the subscription on hold
+    pub max_catchup_intervals: Option<u32>, // Most intervals a single charge may settle
+    pub billing_anchor: Option<u64>, // Cadence grid: charges fall on anchor + k * interval_seconds
+    pub prorated_first_amount: Option<i128>, // Reduced price of the first, partial interval
+    pub reference: Option<u64>, // External invoice/reference ID set at creation
+    pub cancelled_at: Option<u64>, // Set when the subscriber closed it (not when it lapsed)
+    pub total_charged: i128, // Lifetime amount charged, platform fees included
+    pub charge_count: u32,  // Lifetime number of intervals charged
+    pub keeper_fee: i128,   // Paid from the balance to the keeper on each successful charge
+    pub max_charges: Option<u32>, // Intervals after which it completes (1 = one-time purchase)
+}
+
+/// `Stream` as stored by version 1; only read by `migrate`.
+#[contracttype]
+#[derive(Clone)]
+pub struct StreamV1 {
+    pub id: u32,
+    pub sender: Address,
+    pub recipients: Vec<Address>,
+    pub token_contract: Address,
+    pub recipient_rate_per_second: Map<Address, i128>,
+    pub recipient_amount_per_period: Map<Address, i128>,
+    pub period_seconds: u64,
+    pub recipient_accrual_remainder: Map<Address, i128>,
+    pub deposit: i128,
+    pub start_time: u64,
+    pub recipient_last_withdraw: Map<Address, u64>,
+    pub recipient_total_withdrawn: Map<Address, i128>,
+    pub recipient_allocation: Map<Address, i128>,
+    pub is_active: bool,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub paused_at: Option<u64>,
+    pub end_time: Option<u64>,
+    pub cliff_time: Option<u64>,
+}
+
+/// `Stream` as stored before versioning: per-second rates only, one shared deposit pool.
+/// Only read by `migrate`.
+#[contracttype]
+#[derive(Clone)]
+pub struct StreamV0 {
+    pub id: u32,
+    pub sender: Address,
+    pub recipients: Vec<Address>,
+    pub token_contract: Address,
+    pub recipient_rate_per_second: Map<Address, i128>,
+    pub deposit: i128,
+    pub start_time: u64,
+    pub recipient_last_withdraw: Map<Address, u64>,
+    pub recipient_total_withdrawn: Map<Address, i128>,
+    pub is_active: bool,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// `Subscription` as stored by version 1; only read by `migrate`.
+#[contracttype]
+#[derive(Clone)]
+pub struct SubscriptionV1 {
+    pub id: u32,
+    pub subscriber: Address,
+    pub receiver: Address,
+    pub token_contract: Address,
+    pub amount_per_interval: i128,
+    pub interval_seconds: u64,
+    pub next_payment_time: u64,
+    pub active: bool,
+    pub balance: i128,
+    pub title: Option<String>,
+    pub description: Option<String>,
+    pub grace_seconds: u64,
+    pub paused_at: Option<u64>,
+}
+
+/// `Subscription` as stored before versioning (no grace period or pause); only read by
+/// `migrate`.
+#[contracttype]
+#[derive(Clone)]
+pub struct SubscriptionV0 {
+    pub id: u32,
+    pub subscriber: Address,
+    pub receiver: Address,
+    pub token_contract: Address,
+    pub amount_per_interval: i128,
+    pub interval_seconds: u64,
+    pub next_payment_time: u64,
+    pub active: bool,
+    pub balance: i128,
+    pub title: Option<String>,
+    pub description: Option<String>,
+}
+
+/// A subscription together with its billing backlog at the current ledger time
+#[contracttype]
+#[derive(Clone)]
+pub struct SubscriptionView {
+    pub subscription: Subscription,
+    pub intervals_due: u64, // Whole intervals owed now (0 while paused or inactive)
+    pub amount_now_due: i128, // Cost of charging every interval owed
+    pub shortfall: i128,    // How much the balance falls short of `amount_now_due`
+}
+
+/// Billing period unit for `create_subscription_periodic`
+#[contracttype]
+#[derive(Clone, Copy, Debug, Eq, PartialEq)]
+pub enum Period {
+    Daily,
+    Weekly,
+    Monthly, // 30 days
+    Yearly,  // 365 days
+}
+
+/// Length of one `period` in seconds.
+fn period_seconds(period: Period) -> u64 {
+    match period {
+        Period::Daily => SECONDS_PER_DAY,
+        Period::Weekly => SECONDS_PER_WEEK,
+        Period::Monthly => SECONDS_PER_MONTH,
+        Period::Yearly => SECONDS_PER_YEAR,
+    }
+}
+
+#[contract]
+pub struct Streamer;
+
+#[contractimpl]
+impl Streamer {
+    /// Initialize platform admin, optional default token contract and optional platform fee
+    /// collector (fees themselves stay off until `set_stream_fee`/`set_subscription_fee`).
+    /// Call once.
+    pub fn init(
+        env: Env,
+        platform_admin: Address,
+        default_token: Option<Address>,
+        fee_collector: Option<Address>,
+    ) {
+        if env
+            .storage()
+            .persistent()
+            .get::<_, Address>(&DataKey::PlatformAdmin)
+            .is_some()
+        {
+            panic_with_error!(&env, Error::AlreadyInitialized);
+        }
+        env.storage()
+            .persistent()
+            .set(&DataKey::PlatformAdmin, &platform_admin);
+        env.storage()
+            .persistent()
+            .set(&DataKey::NextStreamId, &1u32);
+        env.storage()
+            .persistent()
+            .set(&DataKey::NextSubscriptionId, &1u32);
+        env.storage().persistent().set(&DataKey::Version, &VERSION);
+        if let Some(t) = default_token {
+            env.storage().persistent().set(&DataKey::TokenContract, &t);
+        }
+        if let Some(collector) = fee_collector {
+            env.storage()
+                .persistent()
+                .set(&DataKey::FeeCollector, &collector);
+        }
+    }
+
+    // ===========================
+    // STREAMING: create / withdraw / cancel
+    // ===========================
+
+    /// Create a stream. Transfers `deposit` tokens from the sender to this contract
+    /// and registers a new payment stream with multiple recipients.
+    /// Each recipient accrues their own `amounts_per_period[i]` every `period_seconds`, to the
+    /// second and without rounding the rate down to whole units per second.
+    ///
+    /// `options` carries the optional schedule (see `StreamOptions`): a future start, a fixed
+    /// end, a vesting cliff, a cancellation notice period, recipient acceptance, a
+    /// release gate (see `release_gate`), the cancel policy, and a minimum gap between a
+    /// recipient's withdrawals (earlier ones fail with `NotDueYet`).
+    /// If a platform fee is configured it is deducted from `deposit` before funding the stream.
+    /// A sender can create a limited number of streams per ledger (see
+    /// `set_max_streams_per_ledger`); beyond that this fails with `RateLimited`.
+    ///
+    /// Returns the stream id, or an `Error` describing the first validation failure; every
+    /// input is validated before any tokens move.
+    pub fn create_stream(
+        env: Env,
+        sender: Address,
+        recipients: Vec<Address>,
+        token_contract: Address,
+        amounts_per_period: Vec<i128>, // atomic units for each recipient
+        period_seconds: u64,           // e.g., 30 days in seconds
+        deposit: i128,
+        title: Option<String>,
+        description: Option<String>,
+        options: StreamOptions,
+    ) -> Result<u32, Error> {
+        ensure_initialized(&env)?;
+        // auth
+        sender.require_auth();
+
+        // Validate inputs
+        if recipients.is_empty() {
+            return Err(Error::InvalidParameters); // At least one recipient required
+        }
+
+        // Check lengths and duplicates
+        if recipients.len() != amounts_per_period.len() {
+            return Err(Error::InvalidParameters);
+        }
+        for i in 0..recipients.len() {
+            for j in (i + 1)..recipients.len() {
+                if recipients.get(i).unwrap() == recipients.get(j).unwrap() {
+                    return Err(Error::DuplicateRecipient);
+                }
+            }
+        }
+
+        if period_seconds == 0 || deposit <= 0 {
+            return Err(Error::InvalidParameters);
+        }
+        if !is_token_allowed(&env, &token_contract) {
+            return Err(Error::TokenNotAllowed);
+        }
+        if is_token_frozen(&env, &token_contract) {
+            return Err(Error::TokenFrozen);
+        }
+        // Admin-set floors against dust spam streams; unset or zero means no limit
+        let min_deposit: i128 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::MinDeposit)
+            .unwrap_or(0i128);
+        let min_period_seconds: u64 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::MinPeriodSeconds)
+            .unwrap_or(0u64);
+        if deposit < min_deposit || period_seconds < min_period_seconds {
+            return Err(Error::InvalidParameters);
+        }
+
+        // compute start time
+        let now: u64 = env.ledger().timestamp();
+        let start_time: u64 = match options.start_at {
+            Some(t) if t < now => return Err(Error::InvalidParameters),
+            Some(t) => t,
+            None => now,
+        };
+        let end_time = options.end_time;
+        if let Some(end) = end_time {
+            if end <= start_time {
+                return Err(Error::InvalidParameters);
+            }
+        }
+        let cliff_time = options.cliff_time;
+        if let Some(cliff) = cliff_time {
+            if cliff < start_time {
+                return Err(Error::InvalidParameters);
+            }
+        }
+        if options.gate_approver.is_some() && !options.gated {
+            return Err(Error::InvalidParameters);
+        }
+
+        // Validate every recipient amount and derive the rates before any funds move
+        let recipient_last_withdraw = Map::new(&env);
+        let mut recipient_total_withdrawn = Map::new(&env);
+        let mut recipient_rate_per_second = Map::new(&env);
+        let mut recipient_amount_per_period = Map::new(&env);
+
+        // Accrual runs on amount_per_period / period_seconds exactly, so a rate of under one
+        // unit per second (e.g. per-hour payroll) is fine; the truncated per-second rate is
+        // only derived for reads
+        let normalized_title = normalize_optional_text(title, MAX_TITLE_LEN)?;
+        let normalized_description = normalize_optional_text(description, MAX_DESCRIPTION_LEN)?;
+
+        for i in 0..recipients.len() {
+            let recipient = recipients.get(i).unwrap();
+            let amt = amounts_per_period.get(i).unwrap();
+            if amt <= 0i128 {
+                return Err(Error::InvalidParameters);
+            }
+            let rate_i: i128 = amt / (period_seconds as i128);
+            recipient_rate_per_second.set(recipient.clone(), rate_i);
+            recipient_amount_per_period.set(recipient.clone(), amt);
+            // Initialize last withdraw maps (optional; default on read is start_time)
+            // Initialize totals to 0
+            recipient_total_withdrawn.set(recipient.clone(), 0i128);
+        }
+
+        check_creation_rate(&env, &sender)?;
+
+        // All checks passed: take the platform fee and the deposit
+        let token = TokenClient::new(&env, &token_contract);
+        let contract_addr = env.current_contract_address();
+
+        // Platform fee (if configured) goes to the collector; the rest funds the stream
+        let fee_bps: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamFeeBps)
+            .unwrap_or(0u32);
+        let fee_collector: Option<Address> = env.storage().persistent().get(&DataKey::FeeCollector);
+        let mut fee: i128 = 0i128;
+        if let Some(collector) = fee_collector {
+            fee = deposit
+                .checked_mul(fee_bps as i128)
+                .ok_or(Error::ArithmeticOverflow)?
+                / BPS_DENOMINATOR;
+            if fee > 0 {
+                token.transfer(&sender, &collector, &fee);
+                record_fee(&env, &token_contract, fee);
+            }
+        }
+        let deposit = deposit - fee;
+
+        // Transfer deposit from sender to contract
+        token.transfer(&sender, &contract_addr, &deposit);
+
+        // allocate stream id
+        let mut next_id: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::NextStreamId)
+            .unwrap_or(1u32);
+        let stream_id = next_id;
+
+        let mut stream = Stream {
+            id: stream_id,
+            sender: sender.clone(),
+            recipients: recipients.clone(),
+            token_contract: token_contract.clone(),
+            recipient_rate_per_second,
+            recipient_amount_per_period,
+            period_seconds,
+            recipient_accrual_remainder: Map::new(&env),
+            deposit,
+            start_time,
+            recipient_last_withdraw,
+            recipient_total_withdrawn,
+            recipient_allocation: Map::new(&env),
+            // In acceptance mode the stream only activates once a recipient accepts
+            is_active: !options.require_acceptance,
+            title: normalized_title.clone(),
+            description: normalized_description.clone(),
+            paused_at: None,
+            end_time,
+            cliff_time,
+            cancel_notice_seconds: options.cancel_notice_seconds,
+            cancel_requested_at: None,
+            weights: Map::new(&env),
+            require_acceptance: options.require_acceptance,
+            accepted: Map::new(&env),
+            recipient_max_withdraw: Map::new(&env),
+            reference: options.reference,
+            gated: options.gated,
+            gate_approver: options.gate_approver.clone(),
+            recipient_notify: Map::new(&env),
+            cancel_policy: options.cancel_policy,
+            min_withdraw_interval: options.min_withdraw_interval,
+            recipient_last_withdrawn_at: Map::new(&env),
+            refill_from: None,
+            refill_amount: 0i128,
+        };
+        // Earmark each recipient's share of the deposit up front
+        allocate_by_rate(&mut stream, deposit)?;
+        if stream.is_active {
+            adjust_active_count(&env, &DataKey::ActiveStreams, true);
+        }
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        next_id += 1;
+        env.storage()
+            .persistent()
+            .set(&DataKey::NextStreamId, &next_id);
+
+        // Update user stream indexes
+        // Add to sender's sent streams
+        let sender_clone = sender.clone();
+        let mut sent_streams: Vec<u32> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserSentStreams(sender_clone.clone()))
+            .unwrap_or_else(|| Vec::new(&env));
+        sent_streams.push_back(stream_id);
+        env.storage()
+            .persistent()
+            .set(&DataKey::UserSentStreams(sender_clone), &sent_streams);
+
+        // Add to each recipient's received streams
+        for i in 0..recipients.len() {
+            let recipient = recipients.get(i).unwrap();
+            let recipient_clone = recipient.clone();
+            let mut received_streams: Vec<u32> = env
+                .storage()
+                .persistent()
+                .get(&DataKey::UserReceivedStreams(recipient_clone.clone()))
+                .unwrap_or_else(|| Vec::new(&env));
+            received_streams.push_back(stream_id);
+            env.storage().persistent().set(
+                &DataKey::UserReceivedStreams(recipient_clone.clone()),
+                &received_streams,
+            );
+            extend_persistent(&env, &DataKey::UserReceivedStreams(recipient_clone));
+        }
+
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+        extend_persistent(&env, &DataKey::UserSentStreams(sender.clone()));
+        extend_globals(&env);
+
+        // emit event (include all recipients)
+        env.events().publish(
+            (symbol_short!("strm_crt"), stream_id),
+            (
+                sender,
+                recipients.clone(),
+                deposit,
+                start_time,
+                normalized_title,
+                normalized_description,
+                options.reference,
+            ),
+        );
+
+        Ok(stream_id)
+    }
+
+    /// Create a stream that splits one `total_amount_per_period` across `recipients`
+    /// ("pay this team X per month"). Without `weights` the split is even; with them each
+    /// recipient gets `total * weight / sum(weights)`. Weights must line up with
+    /// `recipients` and be positive. The first recipient absorbs the division remainder, so
+    /// the shares always add up to exactly `total_amount_per_period`. The weights (1 each for
+    /// an even split) are stored on the stream for `update_recipient_weight`.
+    ///
+    /// Everything else behaves as in `create_stream`.
+    pub fn create_split_stream(
+        env: Env,
+        sender: Address,
+        recipients: Vec<Address>,
+        token_contract: Address,
+        total_amount_per_period: i128,
+        period_seconds: u64,
+        deposit: i128,
+        weights: Option<Vec<u32>>,
+        title: Option<String>,
+        description: Option<String>,
+        options: StreamOptions,
+    ) -> Result<u32, Error> {
+        ensure_initialized(&env)?;
+        if recipients.is_empty() || total_amount_per_period <= 0 {
+            return Err(Error::InvalidParameters);
+        }
+
+        let weights = match weights {
+            Some(weights) => weights,
+            None => {
+                let mut even = Vec::new(&env);
+                for _ in 0..recipients.len() {
+                    even.push_back(1u32);
+                }
+                even
+            }
+        };
+        if weights.len() != recipients.len() {
+            return Err(Error::InvalidParameters);
+        }
+        let mut total_weight: i128 = 0i128;
+        for weight in weights.iter() {
+            if weight == 0 {
+                return Err(Error::InvalidParameters);
+            }
+            total_weight += weight as i128;
+        }
+
+        let mut amounts_per_period = Vec::new(&env);
+        let mut assigned: i128 = 0i128;
+        for weight in weights.iter() {
+            let share = total_amount_per_period
+                .checked_mul(weight as i128)
+                .ok_or(Error::ArithmeticOverflow)?
+                / total_weight;
+            amounts_per_period.push_back(share);
+            assigned += share;
+        }
+        let first = amounts_per_period.get(0).unwrap();
+        amounts_per_period.set(0, first + (total_amount_per_period - assigned));
+
+        let stream_id = Self::create_stream(
+            env.clone(),
+            sender,
+            recipients.clone(),
+            token_contract,
+            amounts_per_period,
+            period_seconds,
+            deposit,
+            title,
+            description,
+            options,
+        )?;
+
+        // Remember the weights so `update_recipient_weight` can re-split the total later
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        for i in 0..recipients.len() {
+            stream
+                .weights
+                .set(recipients.get(i).unwrap(), weights.get(i).unwrap());
+        }
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+
+        Ok(stream_id)
+    }
+
+    /// Create a stream paid in native XLM, through the network's Stellar Asset Contract for the
+    /// native asset (resolved here, so callers don't need its address). Everything else
+    /// behaves as in `create_stream`.
+    ///
+    /// Amounts are in stroops: 1 XLM = 10^7 (`NATIVE_DECIMALS`). Rates need not be whole
+    /// stroops per second; accrual runs on `amounts_per_period / period_seconds` exactly.
+    pub fn create_native_stream(
+        env: Env,
+        sender: Address,
+        recipients: Vec<Address>,
+        amounts_per_period: Vec<i128>,
+        period_seconds: u64,
+        deposit: i128,
+        title: Option<String>,
+        description: Option<String>,
+        options: StreamOptions,
+    ) -> Result<u32, Error> {
+        ensure_initialized(&env)?;
+        let native_token = native_token_address(&env);
+        if TokenClient::new(&env, &native_token).decimals() != NATIVE_DECIMALS {
+            return Err(Error::InvalidParameters);
+        }
+
+        Self::create_stream(
+            env,
+            sender,
+            recipients,
+            native_token,
+            amounts_per_period,
+            period_seconds,
+            deposit,
+            title,
+            description,
+            options,
+        )
+    }
+
+    /// Get the address of the native XLM Stellar Asset Contract on this network.
+    pub fn get_native_token(env: Env) -> Address {
+        native_token_address(&env)
+    }
+
+    /// Accept a stream created in acceptance mode (`StreamOptions::require_acceptance`).
+    /// Caller must be the recipient. Their accrual starts now (or at `start_time`, if later),
+    /// and the first acceptance activates the stream.
+    pub fn accept_stream(env: Env, stream_id: u32, recipient: Address) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        recipient.require_auth();
+
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        if !stream.require_acceptance || recipient_accepted(&stream, &recipient) {
+            return Err(Error::InvalidParameters);
+        }
+        if stream.recipients.first_index_of(&recipient).is_none() {
+            return Err(Error::NotARecipient);
+        }
+        // A stream still waiting for its first acceptance is inactive but funded; any other
+        // inactive stream has been closed
+        if !stream.is_active && (!stream.accepted.is_empty() || stream.deposit == 0) {
+            return Err(Error::StreamInactive);
+        }
+
+        let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+        stream.accepted.set(recipient.clone(), true);
+        stream.recipient_last_withdraw.set(recipient.clone(), now);
+        stream
+            .recipient_accrual_remainder
+            .set(recipient.clone(), 0i128);
+        set_stream_active(&env, &mut stream, true);
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events()
+            .publish((symbol_short!("strm_acpt"), stream_id), (recipient, now));
+
+        Ok(())
+    }
+
+    /// Release the gate on a stream created with `StreamOptions::gated`. `caller` must be the
+    /// sender or the stream's gate approver. Accrual starts now for every recipient (or at
+    /// `start_time`, if later); nothing is owed for the time the stream spent gated.
+    pub fn release_gate(env: Env, stream_id: u32, caller: Address) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        caller.require_auth();
+
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        if caller != stream.sender && Some(caller.clone()) != stream.gate_approver {
+            return Err(Error::Unauthorized);
+        }
+        if !stream.gated {
+            return Err(Error::InvalidParameters);
+        }
+        // Cancelled or reclaimed streams have nothing left to release
+        if stream.deposit == 0 {
+            return Err(Error::StreamInactive);
+        }
+
+        let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+        for i in 0..stream.recipients.len() {
+            let r = stream.recipients.get(i).unwrap();
+            stream.recipient_last_withdraw.set(r.clone(), now);
+            stream.recipient_accrual_remainder.set(r, 0i128);
+        }
+        stream.gated = false;
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events()
+            .publish((symbol_short!("strm_gate"), stream_id), (caller, now));
+
+        Ok(())
+    }
+
+    /// Take back the allocation of a recipient who never accepted an acceptance-mode stream.
+    /// Caller must be the sender. The recipient is dropped from the stream and their whole
+    /// allocation is refunded; once nobody is left the stream is closed.
+    ///
+    /// Returns the refunded amount.
+    pub fn reclaim_unaccepted(env: Env, stream_id: u32, recipient: Address) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        let index = stream
+            .recipients
+            .first_index_of(&recipient)
+            .ok_or(Error::NotARecipient)?;
+        if !stream.require_acceptance || recipient_accepted(&stream, &recipient) {
+            return Err(Error::InvalidParameters);
+        }
+        if stream.deposit == 0 {
+            return Err(Error::StreamInactive);
+        }
+        if is_token_frozen(&env, &stream.token_contract) {
+            return Err(Error::TokenFrozen);
+        }
+
+        let refund = recipient_unwithdrawn(&stream, &recipient);
+        stream.recipients.remove(index);
+        stream.recipient_rate_per_second.remove(recipient.clone());
+        stream.recipient_amount_per_period.remove(recipient.clone());
+        stream.recipient_accrual_remainder.remove(recipient.clone());
+        stream.recipient_last_withdraw.remove(recipient.clone());
+        stream.recipient_total_withdrawn.remove(recipient.clone());
+        stream.recipient_allocation.remove(recipient.clone());
+        stream.weights.remove(recipient.clone());
+        stream.recipient_max_withdraw.remove(recipient.clone());
+        stream.recipient_notify.remove(recipient.clone());
+        stream.recipient_last_withdrawn_at.remove(recipient.clone());
+        if stream.recipients.is_empty() {
+            set_stream_active(&env, &mut stream, false);
+            stream.deposit = 0;
+        }
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+        remove_from_index(
+            &env,
+            &DataKey::UserReceivedStreams(recipient.clone()),
+            stream_id,
+        );
+
+        if refund > 0 {
+            let token = TokenClient::new(&env, &stream.token_contract);
+            let contract_addr = env.current_contract_address();
+            token.transfer(&contract_addr, &stream.sender, &refund);
+        }
+
+        env.events().publish(
+            (symbol_short!("strm_rclm"), stream_id),
+            (recipient, refund, env.ledger().timestamp()),
+        );
+
+        Ok(refund)
+    }
+
+    /// Withdraw accrued funds for a stream.
+    /// The recipient parameter specifies which recipient is withdrawing.
+    /// Each recipient can withdraw independently based on their own amount per period.
+    ///
+    /// Returns `Error::NothingToWithdraw` when no funds have accrued since the last withdrawal
+    /// (e.g. a second withdrawal in the same ledger); the token contract is never called with
+    /// a zero amount.
+    /// On streams with a `min_withdraw_interval` this requires the recipient's auth, and a
+    /// withdrawal sooner than that after the recipient's previous one fails with
+    /// `Error::NotDueYet`. Elsewhere anyone may trigger the payout to the recipient.
+    /// A stream with auto-renew configured (`set_stream_refill`) may be refilled as part of
+    /// the withdrawal.
+    pub fn withdraw_stream(env: Env, stream_id: u32, recipient: Address) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        // Only the recipient can spend (and restart) their own withdrawal interval
+        let interval_set = env
+            .storage()
+            .persistent()
+            .get::<_, Stream>(&DataKey::StreamKey(stream_id))
+            .is_some_and(|stream| stream.min_withdraw_interval.is_some());
+        let caller = if interval_set {
+            recipient.require_auth();
+            WithdrawCaller::Recipient
+        } else {
+            WithdrawCaller::Anyone
+        };
+        withdraw_from_stream(&env, stream_id, &recipient, &recipient, None, caller)
+    }
+
+    /// Preview what `withdraw_stream` would transfer to `recipient` right now, without
+    /// changing any state. Returns the same errors `withdraw_stream` would, except that an
+    /// empty withdrawal is reported as `Ok(0)` rather than `Error::NothingToWithdraw`.
+    pub fn preview_withdraw(env: Env, stream_id: u32, recipient: Address) -> Result<i128, Error> {
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        stream_withdrawable(&stream, &recipient, env.ledger().timestamp())
+    }
+
+    /// Preview `withdraw_stream` for every recipient at once, so a keeper can see which
+    /// recipients are worth a transaction. Returns `(recipient, amount)` in recipient order;
+    /// recipients still to accept an acceptance-mode stream show 0. Fails like
+    /// `preview_withdraw` for a missing or inactive stream.
+    pub fn preview_all_withdrawals(
+        env: Env,
+        stream_id: u32,
+    ) -> Result<Vec<(Address, i128)>, Error> {
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        let ledger_now = env.ledger().timestamp();
+        let mut previews = Vec::new(&env);
+        for i in 0..stream.recipients.len() {
+            let r = stream.recipients.get(i).unwrap();
+            let amount = match stream_withdrawable(&stream, &r, ledger_now) {
+                Ok(amount) => amount,
+                Err(Error::NotAccepted) => 0i128,
+                Err(e) => return Err(e),
+            };
+            previews.push_back((r, amount));
+        }
+        Ok(previews)
+    }
+
+    /// Withdraw accrued funds for a stream to a different `destination` address
+    /// (e.g. a cold wallet or treasury contract). Requires the recipient's auth; accounting
+    /// stays keyed on `recipient`.
+    pub fn withdraw_stream_to(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        destination: Address,
+    ) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        recipient.require_auth();
+        withdraw_from_stream(
+            &env,
+            stream_id,
+            &recipient,
+            &destination,
+            None,
+            WithdrawCaller::Recipient,
+        )
+    }
+
+    /// Withdraw a specific `amount` (at most what is currently withdrawable) from a stream.
+    /// Requires the recipient's auth.
+    ///
+    /// `recipient_last_withdraw` only advances by the time it took to accrue `amount`, so the
+    /// rest of the accrual stays claimable: a later full withdrawal nets the same total as if
+    /// everything had been withdrawn at once.
+    pub fn withdraw_stream_amount(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        amount: i128,
+    ) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        recipient.require_auth();
+        withdraw_from_stream(
+            &env,
+            stream_id,
+            &recipient,
+            &recipient,
+            Some(amount),
+            WithdrawCaller::Recipient,
+        )
+    }
+
+    /// Withdraw from several streams in one call for the same recipient.
+    /// Streams that can't be withdrawn from (nothing accrued, inactive, not a recipient,
+    /// missing) are skipped with an amount of 0 instead of aborting the batch. Anyone may
+    /// call this, so a `min_withdraw_interval` is neither checked nor restarted.
+    ///
+    /// Returns (stream_id, amount_withdrawn) for every requested stream, in order.
+    pub fn withdraw_many(env: Env, stream_ids: Vec<u32>, recipient: Address) -> Vec<(u32, i128)> {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        let mut results = Vec::new(&env);
+        for i in 0..stream_ids.len() {
+            let stream_id = stream_ids.get(i).unwrap();
+            let amount = withdraw_from_stream(
+                &env,
+                stream_id,
+                &recipient,
+                &recipient,
+                None,
+                WithdrawCaller::Anyone,
+            )
+            .unwrap_or(0i128);
+            results.push_back((stream_id, amount));
+        }
+        results
+    }
+
+    /// Withdraw everything accrued to `recipient` across all of their received streams.
+    /// Streams with nothing accrued, inactive streams and streams the recipient was removed
+    /// from are skipped. At most `MAX_SWEEP_WITHDRAWALS` streams are paid out per call. Like
+    /// `withdraw_many` this needs no auth and leaves any `min_withdraw_interval` untouched.
+    ///
+    /// Returns (total_withdrawn, more_remaining); when `more_remaining` is true, call again
+    /// to collect from the rest.
+    pub fn withdraw_all_received(env: Env, recipient: Address) -> (i128, bool) {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        let stream_ids = Self::get_user_received_stream_ids(env.clone(), recipient.clone());
+        let mut total: i128 = 0i128;
+        let mut paid: u32 = 0u32;
+        for i in 0..stream_ids.len() {
+            if paid >= MAX_SWEEP_WITHDRAWALS {
+                return (total, true);
+            }
+            let stream_id = stream_ids.get(i).unwrap();
+            if let Ok(amount) = withdraw_from_stream(
+                &env,
+                stream_id,
+                &recipient,
+                &recipient,
+                None,
+                WithdrawCaller::Anyone,
+            ) {
+                total = total.saturating_add(amount);
+                paid += 1;
+            }
+        }
+        (total, false)
+    }
+
+    /// Top up an active stream. Caller must be the sender.
+    /// Transfers `amount` from the sender to this contract and adds it to `deposit`,
+    /// extending how long the stream can keep flowing. Withdrawal history is preserved.
+    ///
+    /// Returns the new deposit total.
+    pub fn top_up_stream(env: Env, stream_id: u32, amount: i128) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        if amount <= 0 {
+            return Err(Error::InvalidParameters);
+        }
+        if is_token_frozen(&env, &stream.token_contract) {
+            return Err(Error::TokenFrozen);
+        }
+
+        // A top-up only extends the stream going forward. If the deposit is already fully
+        // accrued the stream has run dry and cannot be revived retroactively.
+        let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+        if stream_remaining(&stream, now)? <= 0 {
+            return Err(Error::StreamInactive);
+        }
+        // Nothing accrues past end_time, so extra funds could only be refunded
+        if let Some(end_time) = stream.end_time {
+            if env.ledger().timestamp() >= end_time {
+                return Err(Error::StreamInactive);
+            }
+        }
+
+        let token = TokenClient::new(&env, &stream.token_contract);
+        let contract_addr = env.current_contract_address();
+        token.transfer(&stream.sender, &contract_addr, &amount);
+
+        stream.deposit = stream
+            .deposit
+            .checked_add(amount)
+            .ok_or(Error::ArithmeticOverflow)?;
+        allocate_by_rate(&mut stream, amount)?;
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+        extend_persistent(&env, &DataKey::UserSentStreams(stream.sender.clone()));
+
+        env.events().publish(
+            (symbol_short!("strm_top"), stream_id),
+            (stream.sender.clone(), amount, stream.deposit, now),
+        );
+
+        Ok(stream.deposit)
+    }
+
+    /// Add a recipient to an active stream. Caller must be the sender.
+    /// The newcomer accrues from now at `amount_per_period / period_seconds`; the
+    /// not-yet-accrued deposit is re-split across all recipients by rate, so existing
+    /// recipients keep everything accrued so far and the higher total outflow applies
+    /// from the join time forward. `strm_addr` carries the newcomer's rate scaled by
+    /// `RATE_SCALE`, as `recipient_flow_rate` reports it. Split streams re-divide one total
+    /// by weight, so they fail with `InvalidParameters` here.
+    pub fn add_recipient(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        amount_per_period: i128,
+        period_seconds: u64,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        if amount_per_period <= 0 || period_seconds == 0 {
+            return Err(Error::InvalidParameters);
+        }
+        // A weightless newcomer would sit outside the split's shared total
+        if !stream.weights.is_empty() {
+            return Err(Error::InvalidParameters);
+        }
+        for i in 0..stream.recipients.len() {
+            if stream.recipients.get(i).unwrap() == recipient {
+                return Err(Error::DuplicateRecipient);
+            }
+        }
+        let rate_i: i128 = amount_per_period / (period_seconds as i128);
+        // Accrual runs on the stream's own period, so express the amount in it
+        let amt = amount_per_period
+            .checked_mul(stream.period_seconds as i128)
+            .ok_or(Error::ArithmeticOverflow)?
+            / (period_seconds as i128);
+        if amt <= 0i128 {
+            // Rounds to nothing per stream period
+            return Err(Error::RateTooSmall);
+        }
+        let scaled_rate = amt
+            .checked_mul(RATE_SCALE)
+            .ok_or(Error::ArithmeticOverflow)?
+            / stream.period_seconds as i128;
+
+        let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+        if stream_remaining(&stream, now)? <= 0 {
+            return Err(Error::StreamInactive);
+        }
+
+        stream.recipients.push_back(recipient.clone());
+        stream
+            .recipient_rate_per_second
+            .set(recipient.clone(), rate_i);
+        stream
+            .recipient_amount_per_period
+            .set(recipient.clone(), amt);
+        stream.recipient_last_withdraw.set(recipient.clone(), now);
+        stream
+            .recipient_total_withdrawn
+            .set(recipient.clone(), 0i128);
+        rebalance_allocations(&mut stream, now)?;
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        let mut received_streams: Vec<u32> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserReceivedStreams(recipient.clone()))
+            .unwrap_or_else(|| Vec::new(&env));
+        received_streams.push_back(stream_id);
+        env.storage().persistent().set(
+            &DataKey::UserReceivedStreams(recipient.clone()),
+            &received_streams,
+        );
+        extend_persistent(&env, &DataKey::UserReceivedStreams(recipient.clone()));
+
+        env.events().publish(
+            (symbol_short!("strm_addr"), stream_id),
+            (recipient, scaled_rate, now),
+        );
+
+        Ok(())
+    }
+
+    /// Remove a recipient from a stream. Caller must be the sender.
+    /// Their outstanding accrual is paid out first; the part of their allocation that had
+    /// not accrued yet is re-split across the remaining recipients (whose rates are
+    /// unchanged). Removing the last recipient deactivates the stream and refunds the
+    /// not-yet-accrued deposit to the sender.
+    ///
+    /// Returns the amount settled to the removed recipient.
+    pub fn remove_recipient(env: Env, stream_id: u32, recipient: Address) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        let (settled, refund, now) = detach_recipient(&env, stream_id, &recipient)?;
+
+        env.events().publish(
+            (symbol_short!("strm_rmr"), stream_id),
+            (recipient, settled, refund, now),
+        );
+
+        Ok(settled)
+    }
+
+    /// Leave a stream as a recipient (e.g. after leaving the company), forfeiting whatever
+    /// has not accrued yet. Caller must be the recipient. Works like `remove_recipient`: the
+    /// accrued amount is paid out, the forfeited allocation goes back to the stream's pool,
+    /// and the last recipient leaving closes the stream with a refund to the sender. Emits
+    /// `strm_rcls` rather than `strm_rmr`.
+    ///
+    /// Returns the amount settled to the recipient.
+    pub fn recipient_close(env: Env, stream_id: u32, recipient: Address) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        recipient.require_auth();
+
+        let (settled, refund, now) = detach_recipient(&env, stream_id, &recipient)?;
+
+        env.events().publish(
+            (symbol_short!("strm_rcls"), stream_id),
+            (recipient, settled, refund, now),
+        );
+
+        Ok(settled)
+    }
+
+    /// Refund the dust left in an exhausted stream (see `get_stream_dust`) to the sender.
+    /// Caller must be the sender. Each recipient's allocation is trimmed to what they
+    /// withdrew and `deposit` is zeroed, so the same leftover can't be swept twice.
+    ///
+    /// Returns the refunded amount.
+    pub fn sweep_stream_dust(env: Env, stream_id: u32) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if stream.is_active {
+            return Err(Error::StreamStillActive);
+        }
+        if is_token_frozen(&env, &stream.token_contract) {
+            return Err(Error::TokenFrozen);
+        }
+        let dust = stream_dust(&stream);
+        if dust <= 0 {
+            return Err(Error::NothingToWithdraw);
+        }
+
+        for i in 0..stream.recipients.len() {
+            let r = stream.recipients.get(i).unwrap();
+            let withdrawn = stream
+                .recipient_total_withdrawn
+                .get(r.clone())
+                .unwrap_or(0i128);
+            stream.recipient_allocation.set(r, withdrawn);
+        }
+        stream.deposit = 0;
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        let token = TokenClient::new(&env, &stream.token_contract);
+        let contract_addr = env.current_contract_address();
+        token.transfer(&contract_addr, &stream.sender, &dust);
+
+        env.events().publish(
+            (symbol_short!("strm_dust"), stream_id),
+            (stream.sender.clone(), dust, env.ledger().timestamp()),
+        );
+
+        Ok(dust)
+    }
+
+    /// Move a recipient's place in a stream to a new address (e.g. after a key compromise).
+    /// Caller must be the old recipient. Whatever has accrued so far is paid out to the old
+    /// address first; the rate, allocation and withdrawal history then continue under
+    /// `new_recipient`, and the received-stream indexes are updated accordingly.
+    pub fn rotate_recipient(
+        env: Env,
+        stream_id: u32,
+        old_recipient: Address,
+        new_recipient: Address,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        old_recipient.require_auth();
+
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        if stream.recipients.first_index_of(&old_recipient).is_none() {
+            return Err(Error::NotARecipient);
+        }
+        if stream.recipients.first_index_of(&new_recipient).is_some() {
+            return Err(Error::DuplicateRecipient);
+        }
+
+        // Settle what has accrued to the old address so far
+        match withdraw_from_stream(
+            &env,
+            stream_id,
+            &old_recipient,
+            &old_recipient,
+            None,
+            WithdrawCaller::Settlement,
+        ) {
+            Ok(_) | Err(Error::NothingToWithdraw) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        let index = stream.recipients.first_index_of(&old_recipient).unwrap();
+        stream.recipients.set(index, new_recipient.clone());
+        if let Some(v) = stream.recipient_rate_per_second.get(old_recipient.clone()) {
+            stream
+                .recipient_rate_per_second
+                .remove(old_recipient.clone());
+            stream
+                .recipient_rate_per_second
+                .set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream
+            .recipient_amount_per_period
+            .get(old_recipient.clone())
+        {
+            stream
+                .recipient_amount_per_period
+                .remove(old_recipient.clone());
+            stream
+                .recipient_amount_per_period
+                .set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream
+            .recipient_accrual_remainder
+            .get(old_recipient.clone())
+        {
+            stream
+                .recipient_accrual_remainder
+                .remove(old_recipient.clone());
+            stream
+                .recipient_accrual_remainder
+                .set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream.recipient_last_withdraw.get(old_recipient.clone()) {
+            stream.recipient_last_withdraw.remove(old_recipient.clone());
+            stream.recipient_last_withdraw.set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream.recipient_total_withdrawn.get(old_recipient.clone()) {
+            stream
+                .recipient_total_withdrawn
+                .remove(old_recipient.clone());
+            stream
+                .recipient_total_withdrawn
+                .set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream.recipient_allocation.get(old_recipient.clone()) {
+            stream.recipient_allocation.remove(old_recipient.clone());
+            stream.recipient_allocation.set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream.weights.get(old_recipient.clone()) {
+            stream.weights.remove(old_recipient.clone());
+            stream.weights.set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream.accepted.get(old_recipient.clone()) {
+            stream.accepted.remove(old_recipient.clone());
+            stream.accepted.set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream.recipient_max_withdraw.get(old_recipient.clone()) {
+            stream.recipient_max_withdraw.remove(old_recipient.clone());
+            stream.recipient_max_withdraw.set(new_recipient.clone(), v);
+        }
+        if let Some(v) = stream
+            .recipient_last_withdrawn_at
+            .get(old_recipient.clone())
+        {
+            stream
+                .recipient_last_withdrawn_at
+                .remove(old_recipient.clone());
+            stream
+                .recipient_last_withdrawn_at
+                .set(new_recipient.clone(), v);
+        }
+        // The new address opts in to callbacks itself, if it implements them
+        stream.recipient_notify.remove(old_recipient.clone());
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        remove_from_index(
+            &env,
+            &DataKey::UserReceivedStreams(old_recipient.clone()),
+            stream_id,
+        );
+        let mut received_streams: Vec<u32> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::UserReceivedStreams(new_recipient.clone()))
+            .unwrap_or_else(|| Vec::new(&env));
+        received_streams.push_back(stream_id);
+        env.storage().persistent().set(
+            &DataKey::UserReceivedStreams(new_recipient.clone()),
+            &received_streams,
+        );
+        extend_persistent(&env, &DataKey::UserReceivedStreams(new_recipient.clone()));
+
+        env.events().publish(
+            (symbol_short!("strm_rot"), stream_id),
+            (old_recipient, new_recipient, env.ledger().timestamp()),
+        );
+
+        Ok(())
+    }
+
+    /// Opt in to (or out of) the `on_stream_received(stream_id, amount)` callback on each
+    /// withdrawal of `recipient`'s funds from this stream. Caller must be the recipient,
+    /// normally a contract that implements the callback.
+    pub fn set_withdraw_notify(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        enabled: bool,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        recipient.require_auth();
+
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        if stream.recipients.first_index_of(&recipient).is_none() {
+            return Err(Error::NotARecipient);
+        }
+        if enabled {
+            stream.recipient_notify.set(recipient.clone(), true);
+        } else {
+            stream.recipient_notify.remove(recipient.clone());
+        }
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events().publish(
+            (symbol_short!("strm_ntfy"), stream_id),
+            (recipient, enabled, env.ledger().timestamp()),
+        );
+
+        Ok(())
+    }
+
+    /// Configure auto-renew (see `withdraw_stream`): whenever a withdrawal leaves less than
+    /// `refill_amount` to accrue, another `refill_amount` is pulled from `refill_from`, which
+    /// must have approved this contract as spender on the stream's token. Caller must be the
+    /// sender, and `refill_from` must also authorise if it is someone else. `None` turns
+    /// auto-renew off.
+    pub fn set_stream_refill(
+        env: Env,
+        stream_id: u32,
+        refill_from: Option<Address>,
+        refill_amount: i128,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        match &refill_from {
+            Some(from) => {
+                if refill_amount <= 0 {
+                    return Err(Error::InvalidParameters);
+                }
+                if *from != stream.sender {
+                    from.require_auth();
+                }
+                stream.refill_amount = refill_amount;
+            }
+            None => stream.refill_amount = 0i128,
+        }
+        stream.refill_from = refill_from.clone();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events().publish(
+            (symbol_short!("strm_rcfg"), stream_id),
+            (refill_from, stream.refill_amount, env.ledger().timestamp()),
+        );
+
+        Ok(())
+    }
+
+    /// Set (or with `None`, clear) the most `recipient` can ever withdraw from the stream in
+    /// total. Caller must be the sender. The cap can't go below what was already withdrawn;
+    /// once it is reached further withdrawals fail with `NothingToWithdraw`.
+    pub fn set_recipient_withdraw_cap(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        cap: Option<i128>,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if stream.recipients.first_index_of(&recipient).is_none() {
+            return Err(Error::NotARecipient);
+        }
+        match cap {
+            Some(c) => {
+                let withdrawn = stream
+                    .recipient_total_withdrawn
+                    .get(recipient.clone())
+                    .unwrap_or(0i128);
+                if c < withdrawn {
+                    return Err(Error::InvalidParameters);
+                }
+                stream.recipient_max_withdraw.set(recipient.clone(), c);
+            }
+            None => {
+                stream.recipient_max_withdraw.remove(recipient.clone());
+            }
+        }
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events().publish(
+            (symbol_short!("strm_cap"), stream_id),
+            (recipient, cap, env.ledger().timestamp()),
+        );
+
+        Ok(())
+    }
+
+    /// Change a weighted recipient's weight in a split stream. Caller must be the sender.
+    /// The weighted recipients' combined rate stays the same and is re-split as
+    /// `total * weight / total_weight` (first weighted recipient absorbs the remainder).
+    /// Everyone's accrual so far is settled into their carry at the old rates first, and the
+    /// not-yet-accrued deposit is then rebalanced by the new rates.
+    pub fn update_recipient_weight(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        weight: u32,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        if stream.recipients.first_index_of(&recipient).is_none() {
+            return Err(Error::NotARecipient);
+        }
+        // Only recipients of a split stream carry a weight
+        if weight == 0 || stream.weights.get(recipient.clone()).is_none() {
+            return Err(Error::InvalidParameters);
+        }
+        stream.weights.set(recipient.clone(), weight);
+
+        let mut weighted = Vec::new(&env);
+        let mut total_amount: i128 = 0i128;
+        let mut total_weight: i128 = 0i128;
+        for i in 0..stream.recipients.len() {
+            let r = stream.recipients.get(i).unwrap();
+            if let Some(w) = stream.weights.get(r.clone()) {
+                total_amount += stream
+                    .recipient_amount_per_period
+                    .get(r.clone())
+                    .unwrap_or(0i128);
+                total_weight += w as i128;
+                weighted.push_back((r, w));
+            }
+        }
+
+        let period = stream.period_seconds as i128;
+        let mut amounts = Vec::new(&env);
+        let mut assigned: i128 = 0i128;
+        for (_, w) in weighted.iter() {
+            let share = total_amount
+                .checked_mul(w as i128)
+                .ok_or(Error::ArithmeticOverflow)?
+                / total_weight;
+            amounts.push_back(share);
+            assigned += share;
+        }
+        let first = amounts.get(0).unwrap();
+        amounts.set(0, first + (total_amount - assigned));
+        for amt in amounts.iter() {
+            if amt <= 0 {
+                return Err(Error::RateTooSmall);
+            }
+        }
+
+        let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+        for i in 0..weighted.len() {
+            let (r, _) = weighted.get(i).unwrap();
+            let amt = amounts.get(i).unwrap();
+            fold_accrual(&mut stream, &r, now)?;
+            stream.recipient_amount_per_period.set(r.clone(), amt);
+            stream.recipient_rate_per_second.set(r, amt / period);
+        }
+        rebalance_allocations(&mut stream, now)?;
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events().publish(
+            (symbol_short!("strm_wght"), stream_id),
+            (recipient, weight, now),
+        );
+
+        Ok(())
+    }
+
+    /// Change a recipient's rate. Caller must be the sender.
+    /// Accrual at the old rate is settled (paid out) up to now first, so the new rate only
+    /// applies from now on; the not-yet-accrued deposit is then re-split by the new rates.
+    pub fn update_recipient_rate(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+        new_amount_per_period: i128,
+        period_seconds: u64,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        if stream.recipients.first_index_of(&recipient).is_none() {
+            return Err(Error::NotARecipient);
+        }
+        if new_amount_per_period <= 0 || period_seconds == 0 {
+            return Err(Error::InvalidParameters);
+        }
+        let new_rate: i128 = new_amount_per_period / (period_seconds as i128);
+        // Accrual runs on the stream's own period, so express the amount in it
+        let amt = new_amount_per_period
+            .checked_mul(stream.period_seconds as i128)
+            .ok_or(Error::ArithmeticOverflow)?
+            / (period_seconds as i128);
+        if amt <= 0 {
+            // Rounds to nothing per stream period
+            return Err(Error::RateTooSmall);
+        }
+
+        // Settle accrual at the old rate so it isn't retroactively repriced
+        match withdraw_from_stream(
+            &env,
+            stream_id,
+            &recipient,
+            &recipient,
+            None,
+            WithdrawCaller::Settlement,
+        ) {
+            Ok(_) | Err(Error::NothingToWithdraw) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        let now: u64 = accrual_time(&stream, env.ledger().timestamp());
+
+        let old_rate = stream
+            .recipient_rate_per_second
+            .get(recipient.clone())
+            .unwrap_or(0i128);
+        stream
+            .recipient_rate_per_second
+            .set(recipient.clone(), new_rate);
+        // Fold any sub-unit accrual left at the old rate into the carry before re-basing
+        fold_accrual(&mut stream, &recipient, now)?;
+        stream
+            .recipient_amount_per_period
+            .set(recipient.clone(), amt);
+        rebalance_allocations(&mut stream, now)?;
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+
+        env.events().publish(
+            (symbol_short!("strm_rate"), stream_id),
+            (recipient, old_rate, new_rate, now),
+        );
+
+        Ok(())
+    }
+
+    /// Pause a stream. Caller must be the sender.
+    /// Accrual stops at the pause timestamp; recipients can still withdraw what accrued before it.
+    pub fn pause_stream(env: Env, stream_id: u32) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        if stream.paused_at.is_some() {
+            return Err(Error::StreamPaused);
+        }
+
+        let now: u64 = env.ledger().timestamp();
+        stream.paused_at = Some(now);
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+
+        env.events().publish(
+            (Symbol::new(&env, "strm_pause"), stream_id),
+            (stream.sender.clone(), now),
+        );
+
+        Ok(())
+    }
+
+    /// Resume a paused stream. Caller must be the sender.
+    /// Shifts the start and per-recipient last-withdraw baselines (and `end_time` /
+    /// `cliff_time`, if set) forward by the paused duration so nothing accrues for the time
+    /// the stream was paused.
+    pub fn resume_stream(env: Env, stream_id: u32) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        stream.sender.require_auth();
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        let paused_at = stream.paused_at.ok_or(Error::StreamNotPaused)?;
+
+        let now: u64 = env.ledger().timestamp();
+        let paused_for = now - paused_at;
+
+        stream.start_time += paused_for;
+        if let Some(end_time) = stream.end_time {
+            stream.end_time = Some(end_time + paused_for);
+        }
+        if let Some(cliff_time) = stream.cliff_time {
+            stream.cliff_time = Some(cliff_time + paused_for);
+        }
+        for i in 0..stream.recipients.len() {
+            let r = stream.recipients.get(i).unwrap();
+            // Recipients without an entry default to start_time, which was shifted above
+            if let Some(last_withdraw) = stream.recipient_last_withdraw.get(r.clone()) {
+                stream
+                    .recipient_last_withdraw
+                    .set(r, last_withdraw + paused_for);
+            }
+        }
+        stream.paused_at = None;
+        env.storage()
+            .persistent()
+            .set(&DataKey::StreamKey(stream_id), &stream);
+
+        env.events().publish(
+            (Symbol::new(&env, "strm_resum"), stream_id),
+            (stream.sender.clone(), paused_for, now),
+        );
+
+        Ok(())
+    }
+
+    /// Cancel a stream. Caller must be the sender.
+    /// Under the default `CancelPolicy::SettleRecipients` each recipient is first paid what
+    /// they could withdraw right now (nothing before the cliff), so accrued funds are never
+    /// refunded from under them; the rest of the deposit goes back to the sender. Streams
+    /// created with `CancelPolicy::RefundSender` refund everything not yet withdrawn.
+    /// For fixed-term streams this is also how the sender reclaims unspent deposit after `end_time`.
+    ///
+    /// If the stream has a `cancel_notice_seconds`, the first call only records the request
+    /// (`strm_cnotice` event) and caps `end_time` at the end of the notice window, so
+    /// recipients keep accruing until then. Once the window has passed, anyone can call this
+    /// again to refund the then-remaining deposit.
+    ///
+    /// Emits `strm_can` with (sender, refunded, settled_to_recipients, accrual_time).
+    pub fn cancel_stream(env: Env, stream_id: u32) {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        if let Err(e) = cancel_sent_stream(&env, stream_id) {
+            panic_with_error!(&env, e);
+        }
+    }
+
+    /// Kill switch: cancel every active stream `sender` has created, as `cancel_stream` would
+    /// (recipients are paid what they can withdraw, the rest is refunded; streams with a
+    /// cancellation notice get their notice instead and are refunded once it runs out).
+    /// Streams in a frozen token are skipped. Works through one page of the sender's
+    /// sent-stream IDs per call: `limit` (capped at `MAX_PAGE_LIMIT`) from index `start`.
+    ///
+    /// Returns (total_refunded, more_remaining); when `more_remaining` is true, call again
+    /// with `start + limit` to cancel the rest.
+    pub fn cancel_all_sent_streams(
+        env: Env,
+        sender: Address,
+        start: u32,
+        limit: u32,
+    ) -> (i128, bool) {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        sender.require_auth();
+
+        let stream_ids = Self::get_user_sent_stream_ids(env.clone(), sender.clone());
+        let (start, end) = page_range(stream_ids.len(), start, limit);
+        let mut total: i128 = 0i128;
+        for i in start..end {
+            let stream_id = stream_ids.get(i).unwrap();
+            match cancel_sent_stream(&env, stream_id) {
+                Ok(refund) => total = total.saturating_add(refund.unwrap_or(0i128)),
+                // Already closed, notice given and still running, or frozen token
+                Err(Error::StreamInactive) | Err(Error::NotDueYet) | Err(Error::TokenFrozen) => {}
+                Err(e) => panic_with_error!(&env, e),
+            }
+        }
+        (total, end < stream_ids.len())
+    }
+
+    /// Break-glass cancel for compliance or dispute resolution. Requires the platform admin's
+    /// auth instead of the sender's, but settles exactly like `cancel_stream`: recipients are
+    /// paid what they can withdraw and the rest is refunded to the sender.
+    pub fn admin_cancel_stream(env: Env, stream_id: u32) -> Result<i128, Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+
+        let policy = stream.cancel_policy;
+        let (remaining_deposit, settled, now) =
+            settle_cancelled_stream(&env, stream_id, &mut stream, policy)?;
+
+        env.events().publish(
+            (symbol_short!("strm_acan"), stream_id),
+            (
+                admin,
+                stream.sender.clone(),
+                remaining_deposit,
+                settled,
+                now,
+            ),
+        );
+
+        Ok(remaining_deposit)
+    }
+
+    /// Close out a stream whose accrual is over (past `end_time`, or every allocation fully
+    /// accrued) in one call instead of waiting on each recipient to withdraw. Anyone may
+    /// call it. Every recipient is paid their whole accrued, unwithdrawn share, which was
+    /// split by rate when the deposit was allocated, regardless of the order in which
+    /// others withdrew; any deposit that never accrued (an `end_time` cut-off) goes back to
+    /// the sender. `deposit` is zeroed and the stream is dropped from the per-user indexes.
+    ///
+    /// Returns the total paid to recipients.
+    pub fn finalize_stream(env: Env, stream_id: u32) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
+        let mut stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        if !stream.is_active {
+            return Err(Error::StreamInactive);
+        }
+        let ledger_now = env.ledger().timestamp();
+        let ended = match stream.end_time {
+            Some(end_time) => ledger_now >= end_time,
+            None => false,
+        };
+        if !cliff_reached(&stream, ledger_now)
+            || !(ended || stream_remaining(&stream, ledger_now)? <= 0)
+        {
+            return Err(Error::StreamStillActive);
+        }
+
+        let (refunded, settled, now) =
+            settle_cancelled_stream(&env, stream_id, &mut stream, CancelPolicy::SettleRecipients)?;
+        extend_persistent(&env, &DataKey::StreamKey(stream_id));
+        unindex_stream(&env, stream_id, &stream);
+
+        env.events().publish(
+            (symbol_short!("strm_fin"), stream_id),
+            (stream.sender.clone(), settled, refunded, now),
+        );
+
+        Ok(settled)
+    }
+
+    /// Drop a finished (exhausted or cancelled) stream from the sender's and recipients' ID
+    /// indexes so the per-user queries stop reading it. `caller` must be the sender or the
+    /// platform admin. The stream record itself is kept and stays readable via `get_stream`.
+    /// Fails with `StreamStillActive` while the stream still holds escrow, so one awaiting
+    /// acceptance or with dust left to sweep (`sweep_stream_dust`) stays visible.
+    pub fn prune_stream(env: Env, stream_id: u32, caller: Address) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        caller.require_auth();
+
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        let admin: Option<Address> = env.storage().persistent().get(&DataKey::PlatformAdmin);
+        if caller != stream.sender && Some(caller) != admin {
+            return Err(Error::Unauthorized);
+        }
+        // Inactive alone isn't finished: a stream awaiting acceptance holds its full deposit
+        if stream.is_active || stream_dust(&stream) > 0 {
+            return Err(Error::StreamStillActive);
+        }
+
+        unindex_stream(&env, stream_id, &stream);
+
+        env.events().publish(
+            (symbol_short!("strm_prun"), stream_id),
+            (stream.sender.clone(), env.ledger().timestamp()),
+        );
+
+        Ok(())
+    }
+
+    // ===========================
+    // SUBSCRIPTIONS: recurring payments (interval pulls)
+    // ===========================
+
+    /// Deposit funds to a subscription (isolated escrow per subscription)
+    /// Subscriber must authorize (require_auth). Funds are isolated to this specific subscription.
+    pub fn deposit_to_subscription(env: Env, subscription_id: u32, amount: i128) {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        let sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SubscriptionNotFound));
+
+        Self::deposit_to_subscription_from(env, subscription_id, sub.subscriber, amount);
+    }
+
+    /// Deposit funds to a subscription on the subscriber's behalf (e.g. a sponsor or the
+    /// service provider prepaying). `payer` must authorize and the tokens come from them,
+    /// but the amount is credited to the subscription's balance as usual. Refunds on cancel
+    /// still go to the subscriber.
+    pub fn deposit_to_subscription_from(
+        env: Env,
+        subscription_id: u32,
+        payer: Address,
+        amount: i128,
+    ) {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        let mut sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SubscriptionNotFound));
+
+        payer.require_auth();
+
+        if amount <= 0 {
+            panic_with_error!(&env, Error::InvalidParameters);
+        }
+
+        escrow_subscription_deposit(&env, &mut sub, &payer, amount);
+    }
+
+    /// Create a subscription and escrow `initial_deposit` for it in the same call, so the
+    /// subscriber signs once. Takes the same arguments as `create_subscription` (bar
+    /// `reference`, for which the argument list has no room); emits both `sub_crt` and
+    /// `sub_dep`.
+    pub fn create_subscription_funded(
+        env: Env,
+        subscriber: Address,
+        receiver: Address,
+        token_contract: Address,
+        amount_per_interval: i128,
+        interval_seconds: u64,
+        first_payment_time: u64,
+        title: Option<String>,
+        description: Option<String>,
+        grace_seconds: u64,
+        initial_deposit: i128,
+    ) -> u32 {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        if initial_deposit <= 0 {
+            panic_with_error!(&env, Error::InvalidParameters);
+        }
+
+        let sub_id = Self::create_subscription(
+            env.clone(),
+            subscriber,
+            receiver,
+            token_contract,
+            amount_per_interval,
+            interval_seconds,
+            first_payment_time,
+            title,
+            description,
+            grace_seconds,
+            None,
+        );
+
+        let mut sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(sub_id))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SubscriptionNotFound));
+        let subscriber = sub.subscriber.clone();
+        escrow_subscription_deposit(&env, &mut sub, &subscriber, initial_deposit);
+
+        sub_id
+    }
+
+    /// Create a subscription billed on fixed boundaries `billing_anchor + k * interval_seconds`
+    /// (e.g. "the 1st of every month" for a monthly interval), whatever the signup time.
+    /// Charges are taken in arrears at each boundary; the first falls on the first boundary
+    /// after now. With `prorate_first` that first charge only covers the partial interval
+    /// since signup, `amount_per_interval * (first_boundary - now) / interval_seconds`
+    /// (rounded down); otherwise it is a full interval. Other arguments are as in `create_subscription`, without `reference`.
+    pub fn create_subscription_anchored(
+        env: Env,
+        subscriber: Address,
+        receiver: Address,
+        token_contract: Address,
+        amount_per_interval: i128,
+        interval_seconds: u64,
+        billing_anchor: u64,
+        prorate_first: bool,
+        title: Option<String>,
+        description: Option<String>,
+        grace_seconds: u64,
+    ) -> u32 {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        if interval_seconds == 0 {
+            panic_with_error!(&env, Error::InvalidParameters);
+        }
+
+        // First boundary strictly after now, stepping from the anchor in either direction
+        let now: u64 = env.ledger().timestamp();
+        let first_payment_time = next_on_grid(billing_anchor, interval_seconds, now + 1);
+
+        let sub_id = Self::create_subscription(
+            env.clone(),
+            subscriber,
+            receiver,
+            token_contract,
+            amount_per_interval,
+            interval_seconds,
+            first_payment_time,
+            title,
+            description,
+            grace_seconds,
+            None,
+        );
+
+        let mut sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(sub_id))
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SubscriptionNotFound));
+        sub.billing_anchor = Some(billing_anchor);
+        let partial_seconds = first_payment_time - now;
+        if prorate_first && partial_seconds < interval_seconds {
+            let prorated = amount_per_interval
+                .checked_mul(partial_seconds as i128)
+                .unwrap_or_else(|| panic_with_error!(&env, Error::ArithmeticOverflow))
+                / (interval_seconds as i128);
+            sub.prorated_first_amount = Some(prorated);
+        }
+        env.storage()
+            .persistent()
+            .set(&DataKey::SubscriptionKey(sub_id), &sub);
+
+        sub_id
+    }
+
+    /// Create a subscription billed every `count` whole `period`s (e.g. `Period::Monthly`
+    /// with `count` 3 for quarterly) instead of a raw `interval_seconds`. Other arguments are
+    /// as in `create_subscription`, without `reference`.
+    pub fn create_subscription_periodic(
+        env: Env,
+        subscriber: Address,
+        receiver: Address,
+        token_contract: Address,
+        amount_per_interval: i128,
+        period: Period,
+        count: u32,
+        first_payment_time: u64,
+        title: Option<String>,
+        description: Option<String>,
+        grace_seconds: u64,
+    ) -> u32 {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        if count == 0 {
+            panic_with_error!(&env, Error::InvalidParameters);
+        }
+        let interval_seconds = period_seconds(period)
+            .checked_mul(count as u64)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::ArithmeticOverflow));
+
+        Self::create_subscription(
+            env,
+            subscriber,
+            receiver,
+            token_contract,
+            amount_per_interval,
+            interval_seconds,
+            first_payment_time,
+            title,
+            description,
+            grace_seconds,
+            None,
+        )
+    }
+
+    /// Create several subscriptions from `subscriber` to `receiver` in one call, e.g. when a
+    /// merchant imports an existing customer base. Each spec is
+    /// `(amount_per_interval, interval_seconds, first_payment_time)`; the rest is as in
+    /// `create_subscription` with no title, description, grace period or reference. At most
+    /// `MAX_BATCH_SUBSCRIPTIONS` specs per call. Every subscription gets its own ID, index
+    /// entries and `sub_crt` event.
+    ///
+    /// Returns the new subscription IDs, in spec order.
+    pub fn create_subscriptions(
+        env: Env,
+        subscriber: Address,
+        receiver: Address,
+        token_contract: Address,
+        specs: Vec<(i128, u64, u64)>,
+    ) -> Vec<u32> {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        if specs.is_empty() || specs.len() > MAX_BATCH_SUBSCRIPTIONS {
+            panic_with_error!(&env, Error::InvalidParameters);
+        }
+
+        subscriber.require_auth();
+
+        let mut ids: Vec<u32> = Vec::new(&env);
+        for (amount_per_interval, interval_seconds, first_payment_time) in specs.iter() {
+            ids.push_back(register_subscription(
+                &env,
+                subscriber.clone(),
+                receiver.clone(),
+                token_contract.clone(),
+                amount_per_interval,
+                interval_seconds,
+                first_payment_time,
+                None,
+                None,
+                0,
+                None,
+            ));
+        }
+        ids
+    }
+
+    /// Create a subscription. Subscriber must authorize (require_auth).
+    /// This model expects the subscriber to periodically ensure the contract has funds to perform the pull,
+    /// or to have previously transferred allowance/escrow. The sponsor of payments (service owner) receives fixed amounts per interval.
+    ///
+    /// next_payment_time should typically be `now + interval_seconds` or now depending on desired behavior.
+    ///
+    /// `grace_seconds` is how long a payment may stay overdue for lack of funds before
+    /// `charge_subscription` deactivates the subscription. `reference` is an optional external
+    /// invoice/reference ID, stored on the subscription and echoed in `sub_crt`.
+    pub fn create_subscription(
+        env: Env,
+        subscriber: Address,
+        receiver: Address,
+        token_contract: Address,
+        amount_per_interval: i128,
+        interval_seconds: u64,
+        first_payment_time: u64,
+        title: Option<String>,
+        description: Option<String>,
+        grace_seconds: u64,
+        reference: Option<u64>,
+    ) -> u32 {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        subscriber.require_auth();
+
+        register_subscription(
+            &env,
+            subscriber,
+            receiver,
+            token_contract,
+            amount_per_interval,
+            interval_seconds,
+            first_payment_time,
+            title,
+            description,
+            grace_seconds,
+            reference,
+        )
+    }
 
-        // emit event (include all recipients)
-        env.events().publish(
-            (symbol_short!("strm_crt"), stream_id),
-            (
-                sender,
-                recipients.clone(),
-                deposit,
-                start_time,
-                normalized_title,
-                normalized_description,
-            ),
-        );
+    /// Charge (execute) a due subscription. Can be called by anyone (keep it open), but it will transfer
+    /// tokens from contract -> receiver. This assumes the contract already holds the subscriber funds,
+    /// or you have some pull authorization pattern (not implemented here).
+    ///
+    /// The typical pattern: a keeper checks subscriptions whose next_payment_time <= now and triggers this call.
+    ///
+    /// If the escrowed balance can't cover every due interval, only the whole intervals it
+    /// covers are charged (possibly none), `next_payment_time` advances by just those, and a
+    /// `sub_short` event is emitted so the subscriber can be prompted to top up. If the
+    /// outstanding payment is still unfunded more than `grace_seconds` after it fell due, the
+    /// subscription lapses (`active = false`, `sub_lapse` event); its balance stays escrowed.
+    /// With `max_catchup_intervals` set, at most that many intervals are settled per call.
+    /// A charge that empties the escrow of a still-active subscription emits `sub_empty`.
+    /// The charge that reaches `max_charges` completes the subscription: it deactivates, the
+    /// rest of the balance is refunded to the subscriber and `sub_done` is emitted.
+    ///
+    /// Returns the number of intervals actually charged.
+    pub fn charge_subscription(env: Env, subscription_id: u32) -> Result<u64, Error> {
+        ensure_initialized(&env)?;
+        charge_due_subscription(&env, subscription_id, None).map(|(charged, _)| charged)
+    }
 
-        stream_id
+    /// Charge a due subscription like `charge_subscription`, paying the subscription's
+    /// `keeper_fee` (if any) to `keeper` out of the escrowed balance. Intervals are only
+    /// charged while the balance covers both their cost and the fee; the fee is paid once
+    /// per successful charge and never on a charge that settles nothing.
+    pub fn charge_subscription_as_keeper(
+        env: Env,
+        subscription_id: u32,
+        keeper: Address,
+    ) -> Result<u64, Error> {
+        ensure_initialized(&env)?;
+        charge_due_subscription(&env, subscription_id, Some(keeper)).map(|(charged, _)| charged)
     }
 
-    /// Withdraw accrued funds for a stream.
-    /// The recipient parameter specifies which recipient is withdrawing.
-    /// Each recipient can withdraw independently based on their own rate (full rate_per_second).
-    pub fn withdraw_stream(env: Env, stream_id: u32, recipient: Address) -> i128 {
-        // fetch stream
-        let mut stream: Stream = env
+    /// Charge several subscriptions in one transaction (keeper batching).
+    /// Each ID goes through the same path as `charge_subscription`; IDs that are missing,
+    /// inactive, paused or not yet due are skipped rather than aborting the batch.
+    /// Returns `(subscription_id, amount_transferred)` for every input ID, where the amount
+    /// is what the receiver was paid (net of any platform fee; refunds on completion are not
+    /// counted), with 0 for skips and for subscriptions whose balance could not cover a
+    /// single interval.
+    pub fn charge_subscriptions(env: Env, ids: Vec<u32>) -> Vec<(u32, i128)> {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
+        let mut results = Vec::new(&env);
+        for subscription_id in ids.iter() {
+            let transferred = match charge_due_subscription(&env, subscription_id, None) {
+                Ok((_, paid_to_receiver)) => paid_to_receiver,
+                Err(_) => 0,
+            };
+            results.push_back((subscription_id, transferred));
+        }
+        results
+    }
+
+    /// Put a subscription on hold (subscriber must auth). No intervals are charged while
+    /// paused; the escrowed balance and subscription history are kept.
+    pub fn pause_subscription(env: Env, subscription_id: u32) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut sub: Subscription = env
             .storage()
             .persistent()
-            .get(&DataKey::StreamKey(stream_id))
-            .unwrap_or_else(|| panic!());
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
 
-        if !stream.is_active {
-            panic!();
-        }
+        sub.subscriber.require_auth();
 
-        // Verify recipient is in the recipients list
-        let mut is_recipient = false;
-        for i in 0..stream.recipients.len() {
-            let r = stream.recipients.get(i).unwrap();
-            if r == recipient {
-                is_recipient = true;
-                break;
-            }
+        if !sub.active {
+            return Err(Error::SubscriptionInactive);
         }
-        if !is_recipient {
-            panic!(); // Not a recipient of this stream
+        if sub.paused_at.is_some() {
+            return Err(Error::SubscriptionPaused);
         }
 
         let now: u64 = env.ledger().timestamp();
+        sub.paused_at = Some(now);
+        env.storage()
+            .persistent()
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
-        // Get this recipient's last withdrawal time (default to start_time)
-        let last_withdraw = stream
-            .recipient_last_withdraw
-            .get(recipient.clone())
-            .unwrap_or(stream.start_time);
-
-        if now <= last_withdraw {
-            return 0i128;
-        }
+        env.events().publish(
+            (symbol_short!("sub_pause"), subscription_id),
+            (sub.subscriber.clone(), now),
+        );
 
-        // Calculate this recipient's accrued amount using their individual rate
-        let rate_i = stream
-            .recipient_rate_per_second
-            .get(recipient.clone())
-            .unwrap_or(0i128);
-        if rate_i <= 0i128 {
-            panic!();
-        }
-        let elapsed = (now - last_withdraw) as i128;
-        let recipient_accrued = elapsed.saturating_mul(rate_i);
+        Ok(())
+    }
 
-        // Calculate total distributed across ALL recipients
-        // Total outflow rate = sum of per-recipient rates
-        let mut total_outflow_rate: i128 = 0i128;
-        for i in 0..stream.recipients.len() {
-            let r = stream.recipients.get(i).unwrap();
-            let ri = stream.recipient_rate_per_second.get(r).unwrap_or(0i128);
-            total_outflow_rate = total_outflow_rate.saturating_add(ri);
-        }
-        let total_elapsed_from_start = (now - stream.start_time) as i128;
-        let total_distributed = total_elapsed_from_start.saturating_mul(total_outflow_rate);
-        let remaining_deposit = stream.deposit.saturating_sub(total_distributed);
+    /// Resume a paused subscription (subscriber must auth). `next_payment_time` rolls
+    /// forward by the paused duration so the hold isn't back-charged, then on to the next
+    /// point of the subscription's cadence grid (`billing_anchor + k * interval_seconds`),
+    /// so charges keep landing on the original schedule.
+    pub fn resume_subscription(env: Env, subscription_id: u32) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
 
-        // Calculate how much this recipient can withdraw
-        // We need to ensure we don't exceed the remaining deposit
-        // Since each recipient gets the full rate, we need to check if there's enough for this withdrawal
-        let transfer_amount = core::cmp::min(recipient_accrued, remaining_deposit);
+        sub.subscriber.require_auth();
 
-        if transfer_amount <= 0 {
-            panic!(); // Nothing to withdraw
+        if !sub.active {
+            return Err(Error::SubscriptionInactive);
         }
+        let paused_at = sub.paused_at.ok_or(Error::SubscriptionNotPaused)?;
 
-        // TOKEN TRANSFER: contract -> recipient
-        let token = TokenClient::new(&env, &stream.token_contract);
-        let contract_addr = env.current_contract_address();
+        let now: u64 = env.ledger().timestamp();
+        let paused_for = now.saturating_sub(paused_at);
+        sub.next_payment_time = sub.next_payment_time.saturating_add(paused_for);
+        // Snap back onto the cadence grid rather than drifting by the pause length
+        let anchor = sub.billing_anchor.unwrap_or(sub.next_payment_time);
+        sub.next_payment_time = next_on_grid(anchor, sub.interval_seconds, sub.next_payment_time);
+        sub.paused_at = None;
+        env.storage()
+            .persistent()
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
-        token.transfer(&contract_addr, &recipient, &transfer_amount);
+        env.events().publish(
+            (symbol_short!("sub_resum"), subscription_id),
+            (sub.subscriber.clone(), paused_for, sub.next_payment_time),
+        );
 
-        // Update this recipient's last withdrawal time
-        stream.recipient_last_withdraw.set(recipient.clone(), now);
+        Ok(())
+    }
 
-        // Update this recipient's total withdrawn
-        let current_total = stream
-            .recipient_total_withdrawn
-            .get(recipient.clone())
-            .unwrap_or(0i128);
-        let new_total = current_total.saturating_add(transfer_amount);
-        stream
-            .recipient_total_withdrawn
-            .set(recipient.clone(), new_total);
+    /// Cap how many overdue intervals a single `charge_subscription` may settle (subscriber
+    /// must auth), so a long-untouched subscription can't be drained in one lump sum. Each
+    /// charge then moves `next_payment_time` forward by at most `max` intervals. `None`
+    /// removes the cap.
+    pub fn set_max_catchup_intervals(
+        env: Env,
+        subscription_id: u32,
+        max: Option<u32>,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
 
-        // Check if deposit is exhausted (all remaining would be distributed)
-        // Calculate new remaining after this withdrawal
-        let new_remaining = remaining_deposit.saturating_sub(transfer_amount);
-        if new_remaining <= 0 {
-            stream.is_active = false;
-        }
+        sub.subscriber.require_auth();
 
+        if max == Some(0) {
+            return Err(Error::InvalidParameters);
+        }
+        sub.max_catchup_intervals = max;
         env.storage()
             .persistent()
-            .set(&DataKey::StreamKey(stream_id), &stream);
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
         env.events().publish(
-            (symbol_short!("strm_wd"), stream_id),
-            (recipient.clone(), transfer_amount, now),
+            (symbol_short!("sub_catch"), subscription_id),
+            (sub.subscriber.clone(), max),
         );
 
-        transfer_amount
+        Ok(())
     }
 
-    /// Cancel a stream. Caller must be the sender.
-    /// Calculates remaining deposit after all recipients' withdrawals and refunds to sender.
-    pub fn cancel_stream(env: Env, stream_id: u32) {
-        let mut stream: Stream = env
+    /// Set the reward paid to whoever charges this subscription through
+    /// `charge_subscription_as_keeper` (subscriber must auth). The fee comes out of the
+    /// subscription balance on top of the receiver's payment; 0 turns the reward off.
+    pub fn set_keeper_fee(env: Env, subscription_id: u32, keeper_fee: i128) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut sub: Subscription = env
             .storage()
             .persistent()
-            .get(&DataKey::StreamKey(stream_id))
-            .unwrap_or_else(|| panic!());
-
-        // only sender can cancel
-        stream.sender.require_auth();
-
-        if !stream.is_active {
-            panic!();
-        }
-
-        // compute remaining deposit after all recipients' withdrawals
-        let now: u64 = env.ledger().timestamp();
-        // Total outflow rate = sum of per-recipient rates
-        let mut total_outflow_rate: i128 = 0i128;
-        for i in 0..stream.recipients.len() {
-            let r = stream.recipients.get(i).unwrap();
-            let ri = stream.recipient_rate_per_second.get(r).unwrap_or(0i128);
-            total_outflow_rate = total_outflow_rate.saturating_add(ri);
-        }
-        let elapsed_from_start = (now - stream.start_time) as i128;
-        let total_distributed = elapsed_from_start.saturating_mul(total_outflow_rate);
-        let remaining_deposit = stream.deposit.saturating_sub(total_distributed);
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
 
-        let token = TokenClient::new(&env, &stream.token_contract);
-        let contract_addr = env.current_contract_address();
+        sub.subscriber.require_auth();
 
-        // Refund remaining deposit to sender
-        if remaining_deposit > 0 {
-            token.transfer(&contract_addr, &stream.sender, &remaining_deposit);
+        if keeper_fee < 0 {
+            return Err(Error::InvalidParameters);
         }
-
-        // mark inactive
-        stream.is_active = false;
-        stream.deposit = 0;
+        sub.keeper_fee = keeper_fee;
         env.storage()
             .persistent()
-            .set(&DataKey::StreamKey(stream_id), &stream);
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
         env.events().publish(
-            (symbol_short!("strm_can"), stream_id),
-            (stream.sender.clone(), remaining_deposit, now),
+            (symbol_short!("sub_kfee"), subscription_id),
+            (sub.subscriber.clone(), keeper_fee),
         );
-    }
 
-    // ===========================
-    // SUBSCRIPTIONS: recurring payments (interval pulls)
-    // ===========================
+        Ok(())
+    }
 
-    /// Deposit funds to a subscription (isolated escrow per subscription)
-    /// Subscriber must authorize (require_auth). Funds are isolated to this specific subscription.
-    pub fn deposit_to_subscription(env: Env, subscription_id: u32, amount: i128) {
+    /// Limit the subscription to `max` charged intervals in total (subscriber must auth);
+    /// `Some(1)` makes it a one-time purchase. Once the last one is charged the subscription
+    /// deactivates and its remaining balance is refunded to the subscriber. `max` must be
+    /// above the intervals already charged. `None` removes the limit.
+    pub fn set_max_charges(env: Env, subscription_id: u32, max: Option<u32>) -> Result<(), Error> {
+        ensure_initialized(&env)?;
         let mut sub: Subscription = env
             .storage()
             .persistent()
             .get(&DataKey::SubscriptionKey(subscription_id))
-            .unwrap_or_else(|| panic!());
+            .ok_or(Error::SubscriptionNotFound)?;
 
         sub.subscriber.require_auth();
 
-        if amount <= 0 {
-            panic!();
+        if !sub.active {
+            return Err(Error::SubscriptionInactive);
         }
+        if max.is_some_and(|max| max <= sub.charge_count) {
+            return Err(Error::InvalidParameters);
+        }
+        sub.max_charges = max;
+        env.storage()
+            .persistent()
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
-        // Transfer tokens from subscriber to contract
-        let token = TokenClient::new(&env, &sub.token_contract);
-        let contract_addr = env.current_contract_address();
-        token.transfer(&sub.subscriber, &contract_addr, &amount);
+        env.events().publish(
+            (symbol_short!("sub_maxc"), subscription_id),
+            (sub.subscriber.clone(), max),
+        );
+
+        Ok(())
+    }
+
+    /// Change the amount charged per interval. Price changes need mutual consent, so both
+    /// the subscriber and the receiver must authorise. Any interval already due is charged at
+    /// the old amount first; the new amount only applies to later charges. Fails with
+    /// `InsufficientContractBalance` while the balance can't cover that backlog (top up and
+    /// charge first).
+    pub fn update_subscription_amount(
+        env: Env,
+        subscription_id: u32,
+        new_amount: i128,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        if new_amount <= 0 {
+            return Err(Error::InvalidParameters);
+        }
+
+        let mut sub = settle_for_terms_change(&env, subscription_id)?;
 
-        // Update subscription balance (isolated)
-        sub.balance = sub.balance.saturating_add(amount);
+        let old_amount = sub.amount_per_interval;
+        sub.amount_per_interval = new_amount;
         env.storage()
             .persistent()
             .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
         env.events().publish(
-            (symbol_short!("sub_dep"), subscription_id),
-            (sub.subscriber.clone(), amount, sub.balance),
+            (symbol_short!("sub_amt"), subscription_id),
+            (old_amount, new_amount, sub.next_payment_time),
         );
+
+        Ok(())
     }
 
-    /// Create a subscription. Subscriber must authorize (require_auth).
-    /// This model expects the subscriber to periodically ensure the contract has funds to perform the pull,
-    /// or to have previously transferred allowance/escrow. The sponsor of payments (service owner) receives fixed amounts per interval.
-    ///
-    /// next_payment_time should typically be `now + interval_seconds` or now depending on desired behavior.
-    pub fn create_subscription(
+    /// Change the billing interval. Like `update_subscription_amount`, this needs both the
+    /// subscriber and the receiver to authorise, and any due interval is charged under the
+    /// old terms first (failing, as there, while the backlog can't be settled). The already
+    /// scheduled `next_payment_time` is kept; the new interval applies from that payment
+    /// onwards.
+    pub fn update_subscription_interval(
         env: Env,
-        subscriber: Address,
-        receiver: Address,
-        token_contract: Address,
-        amount_per_interval: i128,
-        interval_seconds: u64,
-        first_payment_time: u64,
-        title: Option<String>,
-        description: Option<String>,
-    ) -> u32 {
-        subscriber.require_auth();
-
-        if amount_per_interval <= 0 || interval_seconds == 0 {
-            panic!();
+        subscription_id: u32,
+        new_interval: u64,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        if new_interval == 0 {
+            return Err(Error::InvalidParameters);
         }
 
-        let mut next_id: u32 = env
+        let mut sub = settle_for_terms_change(&env, subscription_id)?;
+
+        let old_interval = sub.interval_seconds;
+        sub.interval_seconds = new_interval;
+        // The new cadence runs from the already scheduled payment
+        sub.billing_anchor = Some(sub.next_payment_time);
+        env.storage()
+            .persistent()
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
+
+        env.events().publish(
+            (symbol_short!("sub_intv"), subscription_id),
+            (old_interval, new_interval, sub.next_payment_time),
+        );
+
+        Ok(())
+    }
+
+    /// Redirect a subscription's future charges to `new_receiver` (e.g. a new treasury).
+    /// Caller must be the current receiver. Intervals already due are charged to the old
+    /// receiver first; the escrowed balance and schedule are unaffected, and the
+    /// received-subscription indexes are updated accordingly.
+    pub fn transfer_subscription_receiver(
+        env: Env,
+        subscription_id: u32,
+        new_receiver: Address,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let sub: Subscription = env
             .storage()
             .persistent()
-            .get(&DataKey::NextSubscriptionId)
-            .unwrap_or(1u32);
-        let sub_id = next_id;
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
 
-        let normalized_title = normalize_optional_text(title, MAX_TITLE_LEN);
-        let normalized_description = normalize_optional_text(description, MAX_DESCRIPTION_LEN);
+        sub.receiver.require_auth();
 
-        let subscription = Subscription {
-            id: sub_id,
-            subscriber: subscriber.clone(),
-            receiver: receiver.clone(),
-            token_contract: token_contract.clone(),
-            amount_per_interval,
-            interval_seconds,
-            next_payment_time: first_payment_time,
-            active: true,
-            balance: 0i128, // Start with zero balance - subscriber must deposit
-            title: normalized_title.clone(),
-            description: normalized_description.clone(),
-        };
+        if new_receiver == sub.receiver {
+            return Err(Error::InvalidParameters);
+        }
 
-        env.storage()
+        // Settle what the old receiver is already owed
+        match charge_due_subscription(&env, subscription_id, None) {
+            Ok(_) | Err(Error::NotDueYet) | Err(Error::SubscriptionInactive) => {}
+            Err(e) => return Err(e),
+        }
+
+        let mut sub: Subscription = env
+            .storage()
             .persistent()
-            .set(&DataKey::SubscriptionKey(sub_id), &subscription);
-        next_id += 1;
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
+        let old_receiver = sub.receiver.clone();
+        sub.receiver = new_receiver.clone();
         env.storage()
             .persistent()
-            .set(&DataKey::NextSubscriptionId, &next_id);
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
 
-        // Update user subscription indexes
-        // Add to subscriber's subscriptions
-        let subscriber_clone = subscriber.clone();
-        let mut subscriber_subs: Vec<u32> = env
-            .storage()
-            .persistent()
-            .get(&DataKey::UserSubscriptions(subscriber_clone.clone()))
-            .unwrap_or_else(|| Vec::new(&env));
-        subscriber_subs.push_back(sub_id);
-        env.storage().persistent().set(
-            &DataKey::UserSubscriptions(subscriber_clone),
-            &subscriber_subs,
+        remove_from_index(
+            &env,
+            &DataKey::UserReceivedSubscriptions(old_receiver.clone()),
+            subscription_id,
         );
-
-        // Add to receiver's received subscriptions
-        let receiver_clone = receiver.clone();
-        let mut receiver_subs: Vec<u32> = env
+        let mut received_subs: Vec<u32> = env
             .storage()
             .persistent()
-            .get(&DataKey::UserReceivedSubscriptions(receiver_clone.clone()))
+            .get(&DataKey::UserReceivedSubscriptions(new_receiver.clone()))
             .unwrap_or_else(|| Vec::new(&env));
-        receiver_subs.push_back(sub_id);
+        received_subs.push_back(subscription_id);
         env.storage().persistent().set(
-            &DataKey::UserReceivedSubscriptions(receiver_clone),
-            &receiver_subs,
+            &DataKey::UserReceivedSubscriptions(new_receiver.clone()),
+            &received_subs,
+        );
+        extend_persistent(
+            &env,
+            &DataKey::UserReceivedSubscriptions(new_receiver.clone()),
         );
 
         env.events().publish(
-            (symbol_short!("sub_crt"), sub_id),
-            (
-                subscriber,
-                receiver,
-                amount_per_interval,
-                interval_seconds,
-                first_payment_time,
-                normalized_title,
-                normalized_description,
-            ),
+            (symbol_short!("sub_rcv"), subscription_id),
+            (old_receiver, new_receiver, env.ledger().timestamp()),
         );
 
-        sub_id
+        Ok(())
     }
 
-    /// Charge (execute) a due subscription. Can be called by anyone (keep it open), but it will transfer
-    /// tokens from contract -> receiver. This assumes the contract already holds the subscriber funds,
-    /// or you have some pull authorization pattern (not implemented here).
-    ///
-    /// The typical pattern: a keeper checks subscriptions whose next_payment_time <= now and triggers this call.
-    pub fn charge_subscription(env: Env, subscription_id: u32) {
+    /// Cancel a subscription (subscriber must auth)
+    /// Refunds any remaining balance to the subscriber. Cancelling one that already lapsed
+    /// (or completed) only refunds what is left of its escrow: it isn't recorded as
+    /// cancelled, so it can't be brought back with `reactivate_subscription`. Fails with
+    /// `TokenFrozen` while the token is frozen and there is a balance to refund.
+    pub fn cancel_subscription(env: Env, subscription_id: u32) {
+        ensure_initialized(&env).unwrap_or_else(|e| panic_with_error!(&env, e));
         let mut sub: Subscription = env
             .storage()
             .persistent()
             .get(&DataKey::SubscriptionKey(subscription_id))
-            .unwrap_or_else(|| panic!());
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SubscriptionNotFound));
 
-        if !sub.active {
-            panic!();
+        sub.subscriber.require_auth();
+
+        let refund_amount = sub.balance;
+        if refund_amount > 0 && is_token_frozen(&env, &sub.token_contract) {
+            panic_with_error!(&env, Error::TokenFrozen);
         }
 
         let now: u64 = env.ledger().timestamp();
-        if now < sub.next_payment_time {
-            panic!();
+        sub.balance = 0;
+        if sub.active {
+            sub.cancelled_at = Some(now);
         }
+        set_subscription_active(&env, &mut sub, false);
+        env.storage()
+            .persistent()
+            .set(&DataKey::SubscriptionKey(subscription_id), &sub);
 
-        // Determine how many intervals are due (in case of backlog)
-        let mut due_intervals: u64 = 1;
-        if now >= sub.next_payment_time + sub.interval_seconds {
-            due_intervals = (now - sub.next_payment_time) / sub.interval_seconds + 1;
+        // Refund remaining balance to subscriber (if any), once the closed subscription is
+        // stored (see `withdraw_from_stream`)
+        if refund_amount > 0 {
+            let token = TokenClient::new(&env, &sub.token_contract);
+            let contract_addr = env.current_contract_address();
+            token.transfer(&contract_addr, &sub.subscriber, &refund_amount);
         }
 
-        // total amount to transfer
-        let amount_to_transfer =
-            (sub.amount_per_interval as i128).saturating_mul(due_intervals as i128);
-
-        // Check subscription balance (isolated per subscription)
-        if sub.balance < amount_to_transfer {
-            panic!();
-        }
+        env.events().publish(
+            (symbol_short!("sub_can"), subscription_id),
+            (
+                sub.subscriber.clone(),
+                sub.receiver.clone(),
+                refund_amount,
+                now,
+            ),
+        );
+    }
 
-        // Transfer from contract to receiver
-        let token = TokenClient::new(&env, &sub.token_contract);
-        let contract_addr = env.current_contract_address();
+    /// Reactivate a subscription its subscriber cancelled, keeping
+    /// its ID and history. Subscriber must auth. Subscriptions that lapsed for lack of funds
+    /// can't be revived this way. Billing restarts at `new_first_payment_time` (not in the
+    /// past); the balance is whatever the cancel left (normally 0), so top it up with
+    /// `deposit_to_subscription` before then.
+    pub fn reactivate_subscription(
+        env: Env,
+        subscription_id: u32,
+        new_first_payment_time: u64,
+    ) -> Result<(), Error> {
+        ensure_initialized(&env)?;
+        let mut sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
 
-        token.transfer(&contract_addr, &sub.receiver, &amount_to_transfer);
+        sub.subscriber.require_auth();
 
-        // Deduct from subscription balance (isolated)
-        sub.balance = sub.balance.saturating_sub(amount_to_transfer);
+        if sub.active || sub.cancelled_at.is_none() {
+            return Err(Error::InvalidParameters);
+        }
+        // A subscription that ran through its `max_charges` is complete, not cancelled
+        if sub.max_charges.is_some_and(|max| sub.charge_count >= max) {
+            return Err(Error::InvalidParameters);
+        }
+        let now: u64 = env.ledger().timestamp();
+        if new_first_payment_time < now {
+            return Err(Error::InvalidParameters);
+        }
 
-        // update next payment time
-        sub.next_payment_time = sub.next_payment_time + due_intervals * sub.interval_seconds;
+        set_subscription_active(&env, &mut sub, true);
+        sub.cancelled_at = None;
+        sub.paused_at = None;
+        sub.prorated_first_amount = None;
+        sub.next_payment_time = new_first_payment_time;
+        sub.billing_anchor = Some(new_first_payment_time);
         env.storage()
             .persistent()
             .set(&DataKey::SubscriptionKey(subscription_id), &sub);
-
-        env.events().publish(
-            (symbol_short!("sub_chrg"), subscription_id),
-            (
-                sub.receiver.clone(),
-                amount_to_transfer,
-                sub.next_payment_time,
-            ),
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
+
+        env.events().publish(
+            (symbol_short!("sub_react"), subscription_id),
+            (sub.subscriber.clone(), new_first_payment_time, now),
         );
+
+        Ok(())
     }
 
-    /// Cancel a subscription (subscriber must auth)
-    /// Refunds any remaining balance to the subscriber
-    pub fn cancel_subscription(env: Env, subscription_id: u32) {
+    /// Reclaim the escrowed balance of a subscription the receiver has stopped charging.
+    /// Subscriber must auth. Allowed once `next_payment_time` is more than `stale_seconds`
+    /// (admin-configurable, `DEFAULT_STALE_SECONDS` if unset) in the past. Unlike
+    /// `cancel_subscription` this only empties the escrow: the subscription keeps its
+    /// `active` flag and schedule, so a fresh `deposit_to_subscription` picks it back up.
+    ///
+    /// Returns the reclaimed amount.
+    pub fn reclaim_stale_subscription(env: Env, subscription_id: u32) -> Result<i128, Error> {
+        ensure_initialized(&env)?;
         let mut sub: Subscription = env
             .storage()
             .persistent()
             .get(&DataKey::SubscriptionKey(subscription_id))
-            .unwrap_or_else(|| panic!());
+            .ok_or(Error::SubscriptionNotFound)?;
 
         sub.subscriber.require_auth();
 
-        let refund_amount = sub.balance;
-
-        // Refund remaining balance to subscriber (if any)
-        if sub.balance > 0 {
-            let token = TokenClient::new(&env, &sub.token_contract);
-            let contract_addr = env.current_contract_address();
-            token.transfer(&contract_addr, &sub.subscriber, &sub.balance);
+        let stale_seconds: u64 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StaleSeconds)
+            .unwrap_or(DEFAULT_STALE_SECONDS);
+        let now: u64 = env.ledger().timestamp();
+        if now <= sub.next_payment_time.saturating_add(stale_seconds) {
+            return Err(Error::NotDueYet);
+        }
+        if sub.balance <= 0 {
+            return Err(Error::NothingToWithdraw);
+        }
+        if is_token_frozen(&env, &sub.token_contract) {
+            return Err(Error::TokenFrozen);
         }
 
+        let reclaimed = sub.balance;
         sub.balance = 0;
-        sub.active = false;
         env.storage()
             .persistent()
             .set(&DataKey::SubscriptionKey(subscription_id), &sub);
+        extend_persistent(&env, &DataKey::SubscriptionKey(subscription_id));
+
+        let token = TokenClient::new(&env, &sub.token_contract);
+        let contract_addr = env.current_contract_address();
+        token.transfer(&contract_addr, &sub.subscriber, &reclaimed);
 
-        let now: u64 = env.ledger().timestamp();
         env.events().publish(
-            (symbol_short!("sub_can"), subscription_id),
-            (
-                sub.subscriber.clone(),
-                sub.receiver.clone(),
-                refund_amount,
-                now,
-            ),
+            (Symbol::new(&env, "sub_reclaim"), subscription_id),
+            (sub.subscriber.clone(), reclaimed, now),
         );
+
+        Ok(reclaimed)
     }
 
     // ===========================
     // RECIPIENT INFO QUERIES
     // ===========================
 
+    /// Get `recipient`'s exact rate in a stream as `(amount_per_period, period_seconds)`,
+    /// which is what accrual uses. Prefer it over `recipient_rate_per_second`, which is
+    /// truncated to whole units per second and reads 0 for slower rates.
+    pub fn get_recipient_rate(
+        env: Env,
+        stream_id: u32,
+        recipient: Address,
+    ) -> Result<(i128, u64), Error> {
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        let amount = stream
+            .recipient_amount_per_period
+            .get(recipient)
+            .ok_or(Error::NotARecipient)?;
+        Ok((amount, stream.period_seconds))
+    }
+
     /// Get detailed information about a specific recipient in a stream.
     /// Returns: (total_withdrawn, current_accrued, last_withdraw_time)
     pub fn get_recipient_info(env: Env, stream_id: u32, recipient: Address) -> (i128, i128, u64) {
@@ -681,7 +4594,7 @@ impl Streamer {
             .storage()
             .persistent()
             .get(&DataKey::StreamKey(stream_id))
-            .unwrap_or_else(|| panic!());
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotFound));
 
         // Verify recipient is in the list
         let mut is_recipient = false;
@@ -693,10 +4606,10 @@ impl Streamer {
             }
         }
         if !is_recipient {
-            panic!(); // Not a recipient
+            panic_with_error!(&env, Error::NotARecipient);
         }
 
-        let now = env.ledger().timestamp();
+        let now = accrual_time(&stream, env.ledger().timestamp());
 
         // Get total withdrawn (default to 0)
         let total_withdrawn = stream
@@ -710,29 +4623,11 @@ impl Streamer {
             .get(recipient.clone())
             .unwrap_or(stream.start_time);
 
-        // Calculate current accrued (not yet withdrawn) using per-recipient rate
-        let rate_i = stream
-            .recipient_rate_per_second
-            .get(recipient.clone())
-            .unwrap_or(0i128);
-        let elapsed = (now - last_withdraw) as i128;
-        let current_accrued = elapsed.saturating_mul(rate_i);
-
-        // Cap accrued by remaining deposit
-        // Total outflow rate = sum of per-recipient rates
-        let mut total_outflow_rate: i128 = 0i128;
-        for i in 0..stream.recipients.len() {
-            let r = stream.recipients.get(i).unwrap();
-            let ri = stream.recipient_rate_per_second.get(r).unwrap_or(0i128);
-            total_outflow_rate = total_outflow_rate.saturating_add(ri);
-        }
-        let total_elapsed_from_start = (now - stream.start_time) as i128;
-        let total_distributed = total_elapsed_from_start.saturating_mul(total_outflow_rate);
-        let remaining_deposit = stream.deposit.saturating_sub(total_distributed);
-
-        // Limit accrued by available deposit (if remaining is negative, cap at 0)
-        let capped_accrued = if remaining_deposit > 0 {
-            core::cmp::min(current_accrued, remaining_deposit)
+        // Calculate current accrued (not yet withdrawn), capped by the recipient's allocation;
+        // nothing is withdrawable before the cliff
+        let capped_accrued = if cliff_reached(&stream, env.ledger().timestamp()) {
+            recipient_accrued(&stream, &recipient, now)
+                .unwrap_or_else(|e| panic_with_error!(&env, e))
         } else {
             0i128
         };
@@ -747,21 +4642,10 @@ impl Streamer {
             .storage()
             .persistent()
             .get(&DataKey::StreamKey(stream_id))
-            .unwrap_or_else(|| panic!());
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotFound));
 
         let mut result = Vec::new(&env);
-        let now = env.ledger().timestamp();
-
-        // Calculate remaining deposit for all recipients (sum of per-recipient rates)
-        let mut total_outflow_rate: i128 = 0i128;
-        for i in 0..stream.recipients.len() {
-            let r = stream.recipients.get(i).unwrap();
-            let ri = stream.recipient_rate_per_second.get(r).unwrap_or(0i128);
-            total_outflow_rate = total_outflow_rate.saturating_add(ri);
-        }
-        let total_elapsed_from_start = (now - stream.start_time) as i128;
-        let total_distributed = total_elapsed_from_start.saturating_mul(total_outflow_rate);
-        let remaining_deposit = stream.deposit.saturating_sub(total_distributed);
+        let now = accrual_time(&stream, env.ledger().timestamp());
 
         for i in 0..stream.recipients.len() {
             let recipient = stream.recipients.get(i).unwrap();
@@ -776,16 +4660,10 @@ impl Streamer {
                 .get(recipient.clone())
                 .unwrap_or(stream.start_time);
 
-            let elapsed = (now - last_withdraw) as i128;
-            let rate_i = stream
-                .recipient_rate_per_second
-                .get(recipient.clone())
-                .unwrap_or(0i128);
-            let current_accrued = elapsed.saturating_mul(rate_i);
-
-            // Cap accrued by remaining deposit (if remaining is negative, cap at 0)
-            let capped_accrued = if remaining_deposit > 0 {
-                core::cmp::min(current_accrued, remaining_deposit)
+            // Cap accrued by this recipient's remaining allocation (zero before the cliff)
+            let capped_accrued = if cliff_reached(&stream, env.ledger().timestamp()) {
+                recipient_accrued(&stream, &recipient, now)
+                    .unwrap_or_else(|e| panic_with_error!(&env, e))
             } else {
                 0i128
             };
@@ -808,14 +4686,256 @@ impl Streamer {
         env.storage()
             .persistent()
             .get(&DataKey::StreamKey(stream_id))
-            .unwrap_or_else(|| panic!())
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotFound))
+    }
+
+    /// Get a stream, or `None` if no stream has this ID (where `get_stream` traps). Named
+    /// so it doesn't clash with the client's generated `try_get_stream`.
+    pub fn get_stream_by_id_safe(env: Env, stream_id: u32) -> Option<Stream> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+    }
+
+    /// Get just a stream's recipients, in creation order, without the per-recipient maps
+    /// that come with `get_stream`.
+    pub fn get_stream_recipients(env: Env, stream_id: u32) -> Vec<Address> {
+        env.storage()
+            .persistent()
+            .get::<_, Stream>(&DataKey::StreamKey(stream_id))
+            .map(|stream| stream.recipients)
+            .unwrap_or_else(|| panic_with_error!(&env, Error::StreamNotFound))
+    }
+
+    /// Get a stream's latest withdrawals (at most `MAX_STREAM_ACTIVITY`), oldest first, so
+    /// wallets can show recent history without an indexer.
+    pub fn get_recent_stream_activity(env: Env, stream_id: u32) -> Vec<ActivitySummary> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::StreamActivity(stream_id))
+            .unwrap_or_else(|| Vec::new(&env))
+    }
+
+    /// Get a stream with its derived state (remaining deposit, current outflow rate and
+    /// whether it is exhausted) computed at the current timestamp.
+    pub fn get_stream_view(env: Env, stream_id: u32) -> Result<StreamView, Error> {
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        let ledger_now = env.ledger().timestamp();
+        let remaining_deposit = if stream.is_active {
+            core::cmp::max(stream_remaining(&stream, ledger_now)?, 0i128)
+        } else {
+            0i128
+        };
+        let ended = match stream.end_time {
+            Some(end_time) => ledger_now >= end_time,
+            None => false,
+        };
+        let is_exhausted = !stream.is_active || ended || remaining_deposit == 0;
+        // Same figure as the per-user flow-rate queries: paused, gated and not-yet-accepted
+        // recipients contribute nothing
+        let total_outflow_rate = stream_flow_rate(&stream, ledger_now);
+
+        Ok(StreamView {
+            stream,
+            remaining_deposit,
+            total_outflow_rate,
+            is_exhausted,
+        })
+    }
+
+    /// Get the deposit that has not yet accrued to any recipient, and the projected
+    /// timestamp at which the stream runs out (see `stream_exhaustion_time`).
+    /// Returns (remaining, exhaustion_time).
+    pub fn get_stream_remaining(env: Env, stream_id: u32) -> Result<(i128, u64), Error> {
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+
+        let now = env.ledger().timestamp();
+        if !stream.is_active {
+            return Ok((0i128, accrual_time(&stream, now)));
+        }
+        let remaining = core::cmp::max(stream_remaining(&stream, now)?, 0i128);
+        Ok((remaining, stream_exhaustion_time(&stream, now)?))
+    }
+
+    /// Get the leftover locked in an inactive stream that no recipient can withdraw any
+    /// more (see `sweep_stream_dust`). Always 0 for active streams.
+    pub fn get_stream_dust(env: Env, stream_id: u32) -> Result<i128, Error> {
+        let stream: Stream = env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(stream_id))
+            .ok_or(Error::StreamNotFound)?;
+        Ok(stream_dust(&stream))
+    }
+
+    /// Get the computed state of a subscription in one call.
+    /// Returns (active, intervals_due, required, balance, next_payment_time), where
+    /// `intervals_due` counts the backlog the same way `charge_subscription` does (0 while
+    /// paused or inactive) and `required` is what charging all of it would cost.
+    pub fn get_subscription_status(
+        env: Env,
+        subscription_id: u32,
+    ) -> Result<(bool, u64, i128, i128, u64), Error> {
+        let sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
+
+        let (intervals_due, required) = subscription_backlog(&sub, env.ledger().timestamp())?;
+
+        Ok((
+            sub.active,
+            intervals_due,
+            required,
+            sub.balance,
+            sub.next_payment_time,
+        ))
+    }
+
+    /// Get a subscription with its billing backlog computed at the current timestamp, e.g.
+    /// "owes 2 intervals, needs 15 more tokens". Counted like `get_subscription_status`.
+    pub fn get_subscription_view(
+        env: Env,
+        subscription_id: u32,
+    ) -> Result<SubscriptionView, Error> {
+        let subscription: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
+
+        let (intervals_due, amount_now_due) =
+            subscription_backlog(&subscription, env.ledger().timestamp())?;
+        let shortfall = core::cmp::max(amount_now_due - subscription.balance, 0i128);
+
+        Ok(SubscriptionView {
+            subscription,
+            intervals_due,
+            amount_now_due,
+            shortfall,
+        })
+    }
+
+    /// Get a subscription's lifetime billing: (total_charged, charge_count), where the total
+    /// includes platform fees and the count is in intervals (a catch-up charge counts each
+    /// interval it settles).
+    pub fn get_subscription_totals(env: Env, subscription_id: u32) -> Result<(i128, u32), Error> {
+        let sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
+        Ok((sub.total_charged, sub.charge_count))
+    }
+
+    /// Get the platform fees collected so far in `token_contract`, across stream deposits and
+    /// subscription charges.
+    pub fn get_collected_fees(env: Env, token_contract: Address) -> i128 {
+        env.storage()
+            .persistent()
+            .get(&DataKey::CollectedFees(token_contract))
+            .unwrap_or(0i128)
+    }
+
+    /// Get the number of decimals `token_contract` uses; every amount and rate in this
+    /// contract is in its atomic units (1 whole token = 10^decimals).
+    pub fn get_token_decimals(env: Env, token_contract: Address) -> u32 {
+        TokenClient::new(&env, &token_contract).decimals()
+    }
+
+    /// Convert `whole_units` of `token_contract` (e.g. 30 USDC) into the atomic units that
+    /// `create_stream` and friends expect, using the token's own decimals.
+    pub fn to_atomic_units(
+        env: Env,
+        token_contract: Address,
+        whole_units: i128,
+    ) -> Result<i128, Error> {
+        let decimals = TokenClient::new(&env, &token_contract).decimals();
+        10i128
+            .checked_pow(decimals)
+            .and_then(|scale| whole_units.checked_mul(scale))
+            .ok_or(Error::ArithmeticOverflow)
+    }
+
+    /// Get this contract's actual holdings of `token_contract`, for reconciliation against
+    /// `get_total_escrowed`.
+    pub fn get_contract_token_balance(env: Env, token_contract: Address) -> i128 {
+        TokenClient::new(&env, &token_contract).balance(&env.current_contract_address())
+    }
+
+    /// Get headline platform metrics without scanning IDs.
+    /// Returns (total_streams, active_streams, total_subscriptions, active_subscriptions);
+    /// totals count everything ever created, from the ID counters.
+    pub fn get_global_stats(env: Env) -> (u32, u32, u32, u32) {
+        let storage = env.storage().persistent();
+        let next_stream_id: u32 = storage.get(&DataKey::NextStreamId).unwrap_or(1u32);
+        let next_subscription_id: u32 = storage.get(&DataKey::NextSubscriptionId).unwrap_or(1u32);
+        (
+            next_stream_id.saturating_sub(1),
+            storage.get(&DataKey::ActiveStreams).unwrap_or(0u32),
+            next_subscription_id.saturating_sub(1),
+            storage.get(&DataKey::ActiveSubscriptions).unwrap_or(0u32),
+        )
+    }
+
+    /// Get what the contract owes out in `token_contract`: the unwithdrawn allocations of
+    /// active streams plus every subscription's escrowed balance (lapsed subscriptions keep
+    /// theirs until cancelled). Scans every stream and subscription ID, so this is meant for
+    /// off-chain simulation rather than on-chain calls.
+    pub fn get_total_escrowed(env: Env, token_contract: Address) -> i128 {
+        let storage = env.storage().persistent();
+        let mut total: i128 = 0i128;
+
+        let next_stream_id: u32 = storage.get(&DataKey::NextStreamId).unwrap_or(1u32);
+        for stream_id in 1..next_stream_id {
+            if let Some(stream) = storage.get::<_, Stream>(&DataKey::StreamKey(stream_id)) {
+                if !stream.is_active || stream.token_contract != token_contract {
+                    continue;
+                }
+                for i in 0..stream.recipients.len() {
+                    let r = stream.recipients.get(i).unwrap();
+                    total = total.saturating_add(recipient_unwithdrawn(&stream, &r));
+                }
+            }
+        }
+
+        let next_subscription_id: u32 = storage.get(&DataKey::NextSubscriptionId).unwrap_or(1u32);
+        for subscription_id in 1..next_subscription_id {
+            if let Some(sub) =
+                storage.get::<_, Subscription>(&DataKey::SubscriptionKey(subscription_id))
+            {
+                if sub.token_contract == token_contract {
+                    total = total.saturating_add(sub.balance);
+                }
+            }
+        }
+
+        total
     }
 
     pub fn get_subscription(env: Env, subscription_id: u32) -> Subscription {
         env.storage()
             .persistent()
             .get(&DataKey::SubscriptionKey(subscription_id))
-            .unwrap_or_else(|| panic!())
+            .unwrap_or_else(|| panic_with_error!(&env, Error::SubscriptionNotFound))
+    }
+
+    /// Get a subscription, or `None` if no subscription has this ID (see
+    /// `get_stream_by_id_safe`).
+    pub fn get_subscription_by_id_safe(env: Env, subscription_id: u32) -> Option<Subscription> {
+        env.storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
     }
 
     /// Get all stream IDs where the user is the sender
@@ -834,6 +4954,30 @@ impl Streamer {
             .unwrap_or_else(|| Vec::new(&env))
     }
 
+    /// Get the IDs of the streams currently paying `recipient`: active streams where they
+    /// still have a positive rate. Exhausted and cancelled streams, and streams they were
+    /// removed from, are left out.
+    pub fn get_recipient_active_stream_ids(env: Env, recipient: Address) -> Vec<u32> {
+        let stream_ids = Self::get_user_received_stream_ids(env.clone(), recipient.clone());
+        let mut active = Vec::new(&env);
+        for stream_id in stream_ids.iter() {
+            if let Some(stream) = env
+                .storage()
+                .persistent()
+                .get::<_, Stream>(&DataKey::StreamKey(stream_id))
+            {
+                let amount = stream
+                    .recipient_amount_per_period
+                    .get(recipient.clone())
+                    .unwrap_or(0i128);
+                if stream.is_active && amount > 0 {
+                    active.push_back(stream_id);
+                }
+            }
+        }
+        active
+    }
+
     /// Get all streams where the user is the sender
     pub fn get_user_sent_streams(env: Env, user: Address) -> Vec<Stream> {
         let stream_ids = Self::get_user_sent_stream_ids(env.clone(), user);
@@ -851,6 +4995,18 @@ impl Streamer {
         streams
     }
 
+    /// Get one page of streams where the user is the sender.
+    /// Returns at most `limit` (capped at `MAX_PAGE_LIMIT`) streams starting at index `start`.
+    pub fn get_user_sent_streams_paged(
+        env: Env,
+        user: Address,
+        start: u32,
+        limit: u32,
+    ) -> Vec<Stream> {
+        let stream_ids = Self::get_user_sent_stream_ids(env.clone(), user);
+        load_streams_page(&env, &stream_ids, start, limit)
+    }
+
     /// Get all streams where the user is the recipient
     pub fn get_user_received_streams(env: Env, user: Address) -> Vec<Stream> {
         let stream_ids = Self::get_user_received_stream_ids(env.clone(), user);
@@ -868,6 +5024,18 @@ impl Streamer {
         streams
     }
 
+    /// Get one page of streams where the user is the recipient.
+    /// Returns at most `limit` (capped at `MAX_PAGE_LIMIT`) streams starting at index `start`.
+    pub fn get_user_received_streams_paged(
+        env: Env,
+        user: Address,
+        start: u32,
+        limit: u32,
+    ) -> Vec<Stream> {
+        let stream_ids = Self::get_user_received_stream_ids(env.clone(), user);
+        load_streams_page(&env, &stream_ids, start, limit)
+    }
+
     /// Get all streams where the user is either sender or recipient
     /// Note: This may include duplicates if a stream has the same user as both sender and recipient
     pub fn get_user_streams(env: Env, user: Address) -> Vec<Stream> {
@@ -893,27 +5061,250 @@ impl Streamer {
         for i in 0..received_ids.len() {
             let stream_id = received_ids.get(i).unwrap();
 
-            // Check if we've already added this stream
-            let mut found = false;
-            for j in 0..seen_ids.len() {
-                if seen_ids.get(j).unwrap() == stream_id {
-                    found = true;
-                    break;
-                }
+            // Check if we've already added this stream
+            let mut found = false;
+            for j in 0..seen_ids.len() {
+                if seen_ids.get(j).unwrap() == stream_id {
+                    found = true;
+                    break;
+                }
+            }
+            if !found {
+                if let Some(stream) = env
+                    .storage()
+                    .persistent()
+                    .get::<_, Stream>(&DataKey::StreamKey(stream_id))
+                {
+                    streams.push_back(stream);
+                    seen_ids.push_back(stream_id);
+                }
+            }
+        }
+
+        streams
+    }
+
+    /// Get `recipient`'s combined flow rate in `token_contract` across every stream currently
+    /// paying them, in whole atomic units per second (rounded down once, after summing; see
+    /// `get_recipient_total_rate_scaled` for the exact figure). Inactive, paused, gated and
+    /// ended streams, streams they have yet to accept or whose allocation for them has fully
+    /// accrued, and streams they were removed from are skipped.
+    pub fn get_recipient_total_rate(env: Env, recipient: Address, token_contract: Address) -> i128 {
+        Self::get_recipient_total_rate_scaled(env, recipient, token_contract) / RATE_SCALE
+    }
+
+    /// `get_recipient_total_rate` in atomic units per second times `RATE_SCALE` (10^9) (see
+    /// `recipient_flow_rate`), so sub-unit-per-second streams still count.
+    pub fn get_recipient_total_rate_scaled(
+        env: Env,
+        recipient: Address,
+        token_contract: Address,
+    ) -> i128 {
+        let stream_ids = Self::get_user_received_stream_ids(env.clone(), recipient.clone());
+        let ledger_now: u64 = env.ledger().timestamp();
+        let mut total: i128 = 0i128;
+        for stream_id in stream_ids.iter() {
+            let stream: Stream = match env
+                .storage()
+                .persistent()
+                .get(&DataKey::StreamKey(stream_id))
+            {
+                Some(stream) => stream,
+                None => continue,
+            };
+            if stream.token_contract != token_contract {
+                continue;
+            }
+            total = total.saturating_add(recipient_flow_rate(&stream, &recipient, ledger_now));
+        }
+        total
+    }
+
+    /// Get the headline figures for a wallet home screen in one call (see `UserDashboard`).
+    /// Reads at most the `MAX_PAGE_LIMIT` most recent IDs of each of the user's sent-stream,
+    /// received-stream and subscription indexes; `truncated` says whether any was longer.
+    /// Rates are summed across tokens, so they only add up meaningfully for single-token users.
+    pub fn get_user_dashboard(env: Env, user: Address) -> UserDashboard {
+        let storage = env.storage().persistent();
+        let ledger_now: u64 = env.ledger().timestamp();
+        let mut dashboard = UserDashboard {
+            incoming_rate: 0i128,
+            outgoing_rate: 0i128,
+            active_sent_streams: 0u32,
+            active_received_streams: 0u32,
+            active_subscriptions: 0u32,
+            subscriber_escrow: 0i128,
+            truncated: false,
+        };
+
+        let sent = Self::get_user_sent_stream_ids(env.clone(), user.clone());
+        let received = Self::get_user_received_stream_ids(env.clone(), user.clone());
+        let subs = Self::get_user_subs_ids(env.clone(), user.clone());
+        dashboard.truncated = sent.len() > MAX_PAGE_LIMIT
+            || received.len() > MAX_PAGE_LIMIT
+            || subs.len() > MAX_PAGE_LIMIT;
+
+        for i in sent.len().saturating_sub(MAX_PAGE_LIMIT)..sent.len() {
+            let stream_id = sent.get(i).unwrap();
+            if let Some(stream) = storage.get::<_, Stream>(&DataKey::StreamKey(stream_id)) {
+                if !stream.is_active {
+                    continue;
+                }
+                dashboard.active_sent_streams += 1;
+                dashboard.outgoing_rate = dashboard
+                    .outgoing_rate
+                    .saturating_add(stream_flow_rate(&stream, ledger_now));
+            }
+        }
+
+        for i in received.len().saturating_sub(MAX_PAGE_LIMIT)..received.len() {
+            let stream_id = received.get(i).unwrap();
+            if let Some(stream) = storage.get::<_, Stream>(&DataKey::StreamKey(stream_id)) {
+                if !stream.is_active {
+                    continue;
+                }
+                dashboard.active_received_streams += 1;
+                dashboard.incoming_rate = dashboard
+                    .incoming_rate
+                    .saturating_add(recipient_flow_rate(&stream, &user, ledger_now));
+            }
+        }
+
+        for i in subs.len().saturating_sub(MAX_PAGE_LIMIT)..subs.len() {
+            let subscription_id = subs.get(i).unwrap();
+            if let Some(sub) =
+                storage.get::<_, Subscription>(&DataKey::SubscriptionKey(subscription_id))
+            {
+                if sub.active {
+                    dashboard.active_subscriptions += 1;
+                }
+                dashboard.subscriber_escrow =
+                    dashboard.subscriber_escrow.saturating_add(sub.balance);
+            }
+        }
+
+        dashboard
+    }
+
+    /// Get one page of the user's streams (sent or received) denominated in `token_contract`.
+    /// Pages run over the user's combined stream IDs (sent first, then received, without
+    /// duplicates): `limit` (capped at `MAX_PAGE_LIMIT`) IDs starting at index `start` are
+    /// read and only matching streams are returned, so a page may hold fewer than `limit`.
+    pub fn get_user_streams_by_token(
+        env: Env,
+        user: Address,
+        token_contract: Address,
+        start: u32,
+        limit: u32,
+    ) -> Vec<Stream> {
+        let mut stream_ids = Self::get_user_sent_stream_ids(env.clone(), user.clone());
+        let received_ids = Self::get_user_received_stream_ids(env.clone(), user);
+        for stream_id in received_ids.iter() {
+            if !stream_ids.contains(stream_id) {
+                stream_ids.push_back(stream_id);
+            }
+        }
+
+        let page = load_streams_page(&env, &stream_ids, start, limit);
+        let mut streams = Vec::new(&env);
+        for stream in page.iter() {
+            if stream.token_contract == token_contract {
+                streams.push_back(stream);
+            }
+        }
+        streams
+    }
+
+    /// Get the IDs of `sender`'s active streams projected to run dry (see
+    /// `get_stream_remaining`) before `deadline`, e.g. to prompt a top-up. Paged over the
+    /// sender's stream IDs like `get_user_streams_by_token`, so a page may hold fewer than
+    /// `limit` IDs.
+    pub fn get_user_streams_ending_before(
+        env: Env,
+        sender: Address,
+        deadline: u64,
+        start: u32,
+        limit: u32,
+    ) -> Vec<u32> {
+        let stream_ids = Self::get_user_sent_stream_ids(env.clone(), sender);
+        let now = env.ledger().timestamp();
+
+        let page = load_streams_page(&env, &stream_ids, start, limit);
+        let mut ending = Vec::new(&env);
+        for stream in page.iter() {
+            if !stream.is_active {
+                continue;
+            }
+            if let Ok(exhaustion) = stream_exhaustion_time(&stream, now) {
+                if exhaustion < deadline {
+                    ending.push_back(stream.id);
+                }
+            }
+        }
+        ending
+    }
+
+    /// Get `sender`'s committed outflow rate in `token_contract` in whole atomic units per
+    /// second, summed over the active streams among one page of their sent-stream IDs
+    /// (`limit`, capped at `MAX_PAGE_LIMIT`, from index `start`) and rounded down once. Paused,
+    /// gated, ended and fully accrued streams contribute 0 (see `recipient_flow_rate`). Sum
+    /// the pages of `get_sender_outflow_rate_scaled` for an exact total.
+    pub fn get_sender_total_outflow_rate(
+        env: Env,
+        sender: Address,
+        token_contract: Address,
+        start: u32,
+        limit: u32,
+    ) -> i128 {
+        Self::get_sender_outflow_rate_scaled(env, sender, token_contract, start, limit) / RATE_SCALE
+    }
+
+    /// `get_sender_total_outflow_rate` in atomic units per second times `RATE_SCALE`, as in
+    /// `get_recipient_total_rate_scaled`.
+    pub fn get_sender_outflow_rate_scaled(
+        env: Env,
+        sender: Address,
+        token_contract: Address,
+        start: u32,
+        limit: u32,
+    ) -> i128 {
+        let stream_ids = Self::get_user_sent_stream_ids(env.clone(), sender);
+        let ledger_now = env.ledger().timestamp();
+
+        let page = load_streams_page(&env, &stream_ids, start, limit);
+        let mut total: i128 = 0i128;
+        for stream in page.iter() {
+            if stream.is_active && stream.token_contract == token_contract {
+                total = total.saturating_add(stream_flow_rate(&stream, ledger_now));
+            }
+        }
+        total
+    }
+
+    /// Get the deposit `sender` still has committed but not yet accrued in `token_contract`
+    /// (see `get_stream_remaining`), summed over the active streams among one page of their
+    /// sent-stream IDs, paged like `get_sender_total_outflow_rate`.
+    pub fn get_sender_total_remaining(
+        env: Env,
+        sender: Address,
+        token_contract: Address,
+        start: u32,
+        limit: u32,
+    ) -> i128 {
+        let stream_ids = Self::get_user_sent_stream_ids(env.clone(), sender);
+        let ledger_now = env.ledger().timestamp();
+
+        let page = load_streams_page(&env, &stream_ids, start, limit);
+        let mut total: i128 = 0i128;
+        for stream in page.iter() {
+            if !stream.is_active || stream.token_contract != token_contract {
+                continue;
             }
-            if !found {
-                if let Some(stream) = env
-                    .storage()
-                    .persistent()
-                    .get::<_, Stream>(&DataKey::StreamKey(stream_id))
-                {
-                    streams.push_back(stream);
-                    seen_ids.push_back(stream_id);
-                }
+            if let Ok(remaining) = stream_remaining(&stream, ledger_now) {
+                total = total.saturating_add(core::cmp::max(remaining, 0i128));
             }
         }
-
-        streams
+        total
     }
 
     /// Get all subscription IDs where the user is the subscriber
@@ -949,6 +5340,18 @@ impl Streamer {
         subscriptions
     }
 
+    /// Get one page of subscriptions where the user is the subscriber.
+    /// Returns at most `limit` (capped at `MAX_PAGE_LIMIT`) subscriptions starting at index `start`.
+    pub fn get_user_subscriptions_paged(
+        env: Env,
+        user: Address,
+        start: u32,
+        limit: u32,
+    ) -> Vec<Subscription> {
+        let subscription_ids = Self::get_user_subs_ids(env.clone(), user);
+        load_subscriptions_page(&env, &subscription_ids, start, limit)
+    }
+
     /// Get all subscriptions where the user is the receiver
     pub fn get_user_received_subscriptions(env: Env, user: Address) -> Vec<Subscription> {
         let subscription_ids = Self::get_user_rcvd_subs_ids(env.clone(), user);
@@ -966,6 +5369,134 @@ impl Streamer {
         subscriptions
     }
 
+    /// Get one page of subscriptions where the user is the receiver.
+    /// Returns at most `limit` (capped at `MAX_PAGE_LIMIT`) subscriptions starting at index `start`.
+    pub fn get_user_rcvd_subs_paged(
+        env: Env,
+        user: Address,
+        start: u32,
+        limit: u32,
+    ) -> Vec<Subscription> {
+        let subscription_ids = Self::get_user_rcvd_subs_ids(env.clone(), user);
+        load_subscriptions_page(&env, &subscription_ids, start, limit)
+    }
+
+    /// Count the active subscriptions among one page of `receiver`'s received subscription
+    /// IDs (at most `limit`, capped at `MAX_PAGE_LIMIT`, starting at index `start`), e.g. for
+    /// a "N active subscribers" badge. Receivers with more subscriptions than one page sum
+    /// the counts over successive pages.
+    pub fn get_rcvd_active_subs_count(env: Env, receiver: Address, start: u32, limit: u32) -> u32 {
+        let subscription_ids = Self::get_user_rcvd_subs_ids(env.clone(), receiver);
+        let page = load_subscriptions_page(&env, &subscription_ids, start, limit);
+        let mut count: u32 = 0;
+        for subscription in page.iter() {
+            if subscription.active {
+                count += 1;
+            }
+        }
+        count
+    }
+
+    /// Enumerate existing stream IDs for indexer backfill.
+    /// Walks at most `limit` (capped at `MAX_PAGE_LIMIT`) IDs starting at `start` and returns
+    /// those that still have a stored stream, skipping gaps.
+    pub fn get_all_stream_ids(env: Env, start: u32, limit: u32) -> Vec<u32> {
+        let next_id: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::NextStreamId)
+            .unwrap_or(1);
+        let (start, end) = id_range(start, limit, next_id);
+
+        let mut ids = Vec::new(&env);
+        for stream_id in start..end {
+            if env
+                .storage()
+                .persistent()
+                .has(&DataKey::StreamKey(stream_id))
+            {
+                ids.push_back(stream_id);
+            }
+        }
+        ids
+    }
+
+    /// Enumerate existing subscription IDs for indexer backfill.
+    /// Walks at most `limit` (capped at `MAX_PAGE_LIMIT`) IDs starting at `start` and returns
+    /// those that still have a stored subscription, skipping gaps.
+    pub fn get_all_subscription_ids(env: Env, start: u32, limit: u32) -> Vec<u32> {
+        let next_id: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::NextSubscriptionId)
+            .unwrap_or(1);
+        let (start, end) = id_range(start, limit, next_id);
+
+        let mut ids = Vec::new(&env);
+        for subscription_id in start..end {
+            if env
+                .storage()
+                .persistent()
+                .has(&DataKey::SubscriptionKey(subscription_id))
+            {
+                ids.push_back(subscription_id);
+            }
+        }
+        ids
+    }
+
+    /// Get when a subscription's next charge falls due, without fetching the whole struct.
+    pub fn get_next_charge_time(env: Env, subscription_id: u32) -> Result<u64, Error> {
+        let sub: Subscription = env
+            .storage()
+            .persistent()
+            .get(&DataKey::SubscriptionKey(subscription_id))
+            .ok_or(Error::SubscriptionNotFound)?;
+        Ok(sub.next_payment_time)
+    }
+
+    /// Check whether charging a subscription now would collect anything: it is active,
+    /// unpaused, due and funded for at least the next interval. Unknown IDs are not chargeable.
+    pub fn is_chargeable(env: Env, subscription_id: u32) -> bool {
+        match env
+            .storage()
+            .persistent()
+            .get::<_, Subscription>(&DataKey::SubscriptionKey(subscription_id))
+        {
+            Some(sub) => subscription_chargeable(&env, &sub, env.ledger().timestamp()),
+            None => false,
+        }
+    }
+
+    /// Find chargeable subscriptions for keeper bots.
+    /// Walks at most `limit` (capped at `MAX_PAGE_LIMIT`) subscription IDs starting at `start`
+    /// and returns those that are active, unpaused, due and funded for at least one interval
+    /// (see `is_chargeable`).
+    /// Keepers page through the ID space by advancing `start` by `limit`.
+    pub fn get_due_subscriptions(env: Env, start: u32, limit: u32) -> Vec<u32> {
+        let next_id: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::NextSubscriptionId)
+            .unwrap_or(1);
+        let now: u64 = env.ledger().timestamp();
+        let (start, end) = id_range(start, limit, next_id);
+
+        let mut due = Vec::new(&env);
+        for subscription_id in start..end {
+            if let Some(sub) = env
+                .storage()
+                .persistent()
+                .get::<_, Subscription>(&DataKey::SubscriptionKey(subscription_id))
+            {
+                if subscription_chargeable(&env, &sub, now) {
+                    due.push_back(subscription_id);
+                }
+            }
+        }
+        due
+    }
+
     /// Get all subscriptions where the user is either subscriber or receiver
     /// Note: This may include duplicates if a subscription has the same user as both subscriber and receiver
     pub fn get_user_subscriptions_all(env: Env, user: Address) -> Vec<Subscription> {
@@ -1014,16 +5545,398 @@ impl Streamer {
         subscriptions
     }
 
+    /// Propose a new platform admin (current admin only). The change takes effect once the
+    /// proposed address calls `accept_admin`; proposing again replaces the pending proposal.
+    pub fn propose_admin(env: Env, new_admin: Address) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::PendingAdmin, &new_admin);
+
+        env.events()
+            .publish((symbol_short!("adm_prop"),), (admin, new_admin));
+        Ok(())
+    }
+
+    /// Accept a pending admin proposal. Must be authorized by the proposed admin.
+    pub fn accept_admin(env: Env) -> Result<(), Error> {
+        let pending: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PendingAdmin)
+            .ok_or(Error::NoPendingAdmin)?;
+        pending.require_auth();
+
+        let previous: Option<Address> = env.storage().persistent().get(&DataKey::PlatformAdmin);
+        env.storage()
+            .persistent()
+            .set(&DataKey::PlatformAdmin, &pending);
+        env.storage().persistent().remove(&DataKey::PendingAdmin);
+        extend_persistent(&env, &DataKey::PlatformAdmin);
+
+        env.events()
+            .publish((symbol_short!("adm_acc"),), (previous, pending));
+        Ok(())
+    }
+
+    /// Withdraw a pending admin proposal (current admin only).
+    pub fn cancel_admin_proposal(env: Env) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        let pending: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PendingAdmin)
+            .ok_or(Error::NoPendingAdmin)?;
+        env.storage().persistent().remove(&DataKey::PendingAdmin);
+
+        env.events()
+            .publish((symbol_short!("adm_can"),), (admin, pending));
+        Ok(())
+    }
+
+    /// Configure the platform fee taken from each subscription charge (admin only).
+    /// `bps` is in basis points of the charged amount and capped at `MAX_FEE_BPS`. The fee is
+    /// deducted from the receiver's payout (the subscriber still pays the listed price) and
+    /// sent to the fee collector, which is shared with the stream fee. Passing `collector`
+    /// replaces it; with `None` the current one is kept. A non-zero fee without any collector
+    /// is rejected with `InvalidParameters`.
+    pub fn set_subscription_fee(
+        env: Env,
+        bps: u32,
+        collector: Option<Address>,
+    ) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        if bps > MAX_FEE_BPS {
+            return Err(Error::InvalidParameters);
+        }
+        let collector: Option<Address> =
+            collector.or_else(|| env.storage().persistent().get(&DataKey::FeeCollector));
+        match collector {
+            Some(collector) => env
+                .storage()
+                .persistent()
+                .set(&DataKey::FeeCollector, &collector),
+            // Fees with nowhere to go would be silently skipped
+            None if bps > 0 => return Err(Error::InvalidParameters),
+            None => {}
+        }
+
+        env.storage().persistent().set(&DataKey::SubFeeBps, &bps);
+        Ok(())
+    }
+
+    /// Configure the platform fee taken from stream deposits at creation (admin only).
+    /// `bps` is in basis points of the deposit and capped at `MAX_FEE_BPS`; fees are
+    /// transferred to the fee collector, which `collector` sets as in `set_subscription_fee`.
+    pub fn set_stream_fee(env: Env, bps: u32, collector: Option<Address>) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        if bps > MAX_FEE_BPS {
+            return Err(Error::InvalidParameters);
+        }
+        let collector: Option<Address> =
+            collector.or_else(|| env.storage().persistent().get(&DataKey::FeeCollector));
+        match collector {
+            Some(collector) => env
+                .storage()
+                .persistent()
+                .set(&DataKey::FeeCollector, &collector),
+            // Fees with nowhere to go would be silently skipped
+            None if bps > 0 => return Err(Error::InvalidParameters),
+            None => {}
+        }
+
+        env.storage().persistent().set(&DataKey::StreamFeeBps, &bps);
+        Ok(())
+    }
+
+    /// Get the current platform fee settings: (stream_fee_bps, subscription_fee_bps,
+    /// fee_collector).
+    pub fn get_fee_config(env: Env) -> (u32, u32, Option<Address>) {
+        let storage = env.storage().persistent();
+        (
+            storage.get(&DataKey::StreamFeeBps).unwrap_or(0u32),
+            storage.get(&DataKey::SubFeeBps).unwrap_or(0u32),
+            storage.get(&DataKey::FeeCollector),
+        )
+    }
+
+    /// Set the smallest deposit `create_stream` accepts (admin only). 0 removes the floor.
+    pub fn set_min_deposit(env: Env, min_deposit: i128) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        if min_deposit < 0 {
+            return Err(Error::InvalidParameters);
+        }
+        env.storage()
+            .persistent()
+            .set(&DataKey::MinDeposit, &min_deposit);
+        extend_persistent(&env, &DataKey::MinDeposit);
+        Ok(())
+    }
+
+    /// Set the shortest `period_seconds` `create_stream` accepts (admin only). 0 removes the floor.
+    pub fn set_min_period_seconds(env: Env, min_period_seconds: u64) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::MinPeriodSeconds, &min_period_seconds);
+        extend_persistent(&env, &DataKey::MinPeriodSeconds);
+        Ok(())
+    }
+
+    /// Set how many streams one sender may create per ledger (admin only). 0 removes the cap;
+    /// until this is called the cap is `DEFAULT_MAX_STREAMS_PER_LEDGER`.
+    pub fn set_max_streams_per_ledger(env: Env, max: u32) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::MaxStreamsPerLedger, &max);
+        extend_persistent(&env, &DataKey::MaxStreamsPerLedger);
+        Ok(())
+    }
+
+    /// Set how long a subscription may go uncharged past `next_payment_time` before the
+    /// subscriber can reclaim its escrow with `reclaim_stale_subscription` (admin only).
+    pub fn set_stale_seconds(env: Env, stale_seconds: u64) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        env.storage()
+            .persistent()
+            .set(&DataKey::StaleSeconds, &stale_seconds);
+        extend_persistent(&env, &DataKey::StaleSeconds);
+        Ok(())
+    }
+
+    /// Add a token to the creation allow-list (admin only).
+    /// Once the list is non-empty, only listed tokens can back new streams and subscriptions.
+    pub fn allow_token(env: Env, token: Address) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        let mut allowed: Map<Address, bool> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::AllowedTokens)
+            .unwrap_or(Map::new(&env));
+        allowed.set(token, true);
+        env.storage()
+            .persistent()
+            .set(&DataKey::AllowedTokens, &allowed);
+        extend_persistent(&env, &DataKey::AllowedTokens);
+        Ok(())
+    }
+
+    /// Remove a token from the creation allow-list (admin only).
+    /// Existing streams and subscriptions using it are unaffected. Removing the last entry
+    /// makes creation permissionless again.
+    pub fn disallow_token(env: Env, token: Address) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        let mut allowed: Map<Address, bool> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::AllowedTokens)
+            .unwrap_or(Map::new(&env));
+        allowed.remove(token);
+        env.storage()
+            .persistent()
+            .set(&DataKey::AllowedTokens, &allowed);
+        extend_persistent(&env, &DataKey::AllowedTokens);
+        Ok(())
+    }
+
+    /// Freeze a token discovered to be compromised (admin only). While frozen, withdrawals,
+    /// subscription charges, new streams and subscriptions, top-ups and deposits in it fail
+    /// with `TokenFrozen`, and so does every path that pays the token out: cancels and
+    /// finalizes, recipient removals, rotations and re-rates, refunds of unaccepted
+    /// allocations and dust, subscription cancels and stale reclaims. Funds stay escrowed
+    /// until `unfreeze_token`.
+    pub fn freeze_token(env: Env, token: Address) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        let key = DataKey::FrozenTokens(token);
+        env.storage().persistent().set(&key, &true);
+        extend_persistent(&env, &key);
+        Ok(())
+    }
+
+    /// Lift a `freeze_token` (admin only).
+    pub fn unfreeze_token(env: Env, token: Address) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        env.storage()
+            .persistent()
+            .remove(&DataKey::FrozenTokens(token));
+        Ok(())
+    }
+
+    /// Get the tokens on the creation allow-list (empty means any token is accepted)
+    pub fn get_allowed_tokens(env: Env) -> Vec<Address> {
+        let allowed: Map<Address, bool> = env
+            .storage()
+            .persistent()
+            .get(&DataKey::AllowedTokens)
+            .unwrap_or(Map::new(&env));
+        allowed.keys()
+    }
+
+    /// Replace the contract code with the already-uploaded wasm `new_wasm_hash` (admin only).
+    /// Storage is kept as-is; call `migrate` afterwards if the new code changes its layout.
+    pub fn upgrade(env: Env, new_wasm_hash: BytesN<32>) -> Result<(), Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        env.deployer().update_current_contract_wasm(new_wasm_hash);
+        Ok(())
+    }
+
+    /// Bring stored data up to `VERSION` after an upgrade (admin only).
+    /// Each step transforms one version into the next; running it again once up to date is a
+    /// no-op. Version 1 only started recording the version, so there is no step for it.
+    /// Moving to 2 rewrites every stream and subscription still stored in the version 0 or
+    /// 1 layout (see `StreamV1`), checking at most `MAX_MIGRATE_RECORDS` of them per call;
+    /// records in any other layout are left as they are. Until it finishes, legacy records
+    /// can't be read by the rest of the contract.
+    ///
+    /// Returns the version now recorded in storage. That stays the old one while records are
+    /// left to check, so call again until it returns `VERSION`.
+    pub fn migrate(env: Env) -> Result<u32, Error> {
+        let admin: Address = env
+            .storage()
+            .persistent()
+            .get(&DataKey::PlatformAdmin)
+            .ok_or(Error::NotInitialized)?;
+        admin.require_auth();
+
+        let stored: u32 = env
+            .storage()
+            .persistent()
+            .get(&DataKey::Version)
+            .unwrap_or(0u32);
+        if stored >= VERSION {
+            return Ok(stored);
+        }
+
+        if stored < 2 {
+            let storage = env.storage().persistent();
+            let (mut next_stream, mut next_sub): (u32, u32) = storage
+                .get(&DataKey::MigrationCursor)
+                .unwrap_or((1u32, 1u32));
+            let stream_end: u32 = storage.get(&DataKey::NextStreamId).unwrap_or(1u32);
+            let sub_end: u32 = storage.get(&DataKey::NextSubscriptionId).unwrap_or(1u32);
+
+            let mut checked: u32 = 0u32;
+            while next_stream < stream_end && checked < MAX_MIGRATE_RECORDS {
+                migrate_stream_record(&env, next_stream);
+                next_stream += 1;
+                checked += 1;
+            }
+            while next_sub < sub_end && checked < MAX_MIGRATE_RECORDS {
+                migrate_subscription_record(&env, next_sub);
+                next_sub += 1;
+                checked += 1;
+            }
+
+            if next_stream < stream_end || next_sub < sub_end {
+                storage.set(&DataKey::MigrationCursor, &(next_stream, next_sub));
+                extend_persistent(&env, &DataKey::MigrationCursor);
+                return Ok(stored);
+            }
+            storage.remove(&DataKey::MigrationCursor);
+        }
+
+        env.storage().persistent().set(&DataKey::Version, &VERSION);
+        extend_globals(&env);
+        Ok(VERSION)
+    }
+
+    /// Version of the contract code currently deployed (see `migrate` for the stored version).
+    pub fn version(_env: Env) -> u32 {
+        VERSION
+    }
+
     // Admin utility to set/replace token contract default (if you use a global default)
     pub fn set_token_contract(env: Env, token: Address) {
         let admin: Address = env
             .storage()
             .persistent()
             .get(&DataKey::PlatformAdmin)
-            .unwrap_or_else(|| panic!());
+            .unwrap_or_else(|| panic_with_error!(&env, Error::NotInitialized));
         admin.require_auth();
         env.storage()
             .persistent()
             .set(&DataKey::TokenContract, &token);
     }
 }
+
+#[cfg(test)]
+mod test;
@@ -0,0 +1,3741 @@
+extern crate std;
+
+use crate::*;
+use soroban_sdk::testutils::{Address as _, Ledger};
+use soroban_sdk::{contract, contractimpl, contracttype, token, vec, Address, Env, Vec};
+
+struct Ctx {
+    env: Env,
+    c: StreamerClient<'static>,
+    tok: Address,
+    admin: Address,
+}
+
+/// Initialized contract at ledger time 1000 with all auths mocked and a Stellar Asset
+/// Contract token `tok` administered by `admin`.
+fn setup() -> Ctx {
+    let env = Env::default();
+    env.mock_all_auths();
+    env.ledger().set_timestamp(1000);
+    let admin = Address::generate(&env);
+    let sac = env.register_stellar_asset_contract_v2(admin.clone());
+    let tok = sac.address();
+    let id = env.register(Streamer, ());
+    let c = StreamerClient::new(&env, &id);
+    c.init(&admin, &None, &None);
+    Ctx { env, c, tok, admin }
+}
+
+fn mint(x: &Ctx, to: &Address, amt: i128) {
+    token::StellarAssetClient::new(&x.env, &x.tok).mint(to, &amt);
+}
+
+fn bal(x: &Ctx, a: &Address) -> i128 {
+    token::Client::new(&x.env, &x.tok).balance(a)
+}
+
+fn adv(x: &Ctx, s: u64) {
+    let t = x.env.ledger().timestamp();
+    x.env.ledger().set_timestamp(t + s);
+}
+
+fn opts(start_at: Option<u64>, end_time: Option<u64>, cliff_time: Option<u64>) -> StreamOptions {
+    StreamOptions {
+        start_at,
+        end_time,
+        cliff_time,
+        cancel_notice_seconds: None,
+        require_acceptance: false,
+        reference: None,
+        gated: false,
+        gate_approver: None,
+        cancel_policy: CancelPolicy::SettleRecipients,
+        min_withdraw_interval: None,
+    }
+}
+
+const S: i128 = RATE_SCALE;
+
+fn mksub(x: &Ctx, subr: &Address, rcv: &Address, amt: i128, interval: u64, first: u64) -> u32 {
+    x.c.create_subscription(
+        subr, rcv, &x.tok, &amt, &interval, &first, &None, &None, &0, &None,
+    )
+}
+
+mod vault {
+    use soroban_sdk::{contract, contractimpl, symbol_short, Env};
+    #[contract]
+    pub struct Vault;
+    #[contractimpl]
+    impl Vault {
+        pub fn on_stream_received(env: Env, stream_id: u32, amount: i128) {
+            env.storage()
+                .instance()
+                .set(&symbol_short!("got"), &(stream_id, amount));
+        }
+        pub fn got(env: Env) -> Option<(u32, i128)> {
+            env.storage().instance().get(&symbol_short!("got"))
+        }
+    }
+    #[contract]
+    pub struct BadVault;
+    #[contractimpl]
+    impl BadVault {
+        pub fn on_stream_received(_env: Env, _stream_id: u32, _amount: i128) {
+            panic!("nope")
+        }
+    }
+}
+
+#[contracttype]
+enum MockKey {
+    Balance(Address),
+    Transfers,
+    Watch,
+    Seen,
+}
+
+/// Minimal token that records every transfer amount and can be told to look at the
+/// streamer's stored stream from inside `transfer`, i.e. while a payout is in flight.
+#[contract]
+struct MockToken;
+
+#[contractimpl]
+impl MockToken {
+    pub fn mint(env: Env, to: Address, amount: i128) {
+        let key = MockKey::Balance(to);
+        let balance: i128 = env.storage().instance().get(&key).unwrap_or(0);
+        env.storage().instance().set(&key, &(balance + amount));
+    }
+
+    pub fn balance(env: Env, id: Address) -> i128 {
+        env.storage()
+            .instance()
+            .get(&MockKey::Balance(id))
+            .unwrap_or(0)
+    }
+
+    pub fn decimals(_env: Env) -> u32 {
+        7
+    }
+
+    pub fn transfer(env: Env, from: Address, to: Address, amount: i128) {
+        from.require_auth();
+        let from_balance = Self::balance(env.clone(), from.clone());
+        if from_balance < amount {
+            panic!("insufficient balance");
+        }
+        env.storage()
+            .instance()
+            .set(&MockKey::Balance(from), &(from_balance - amount));
+        Self::mint(env.clone(), to.clone(), amount);
+
+        let mut transfers: Vec<i128> = Self::transfers(env.clone());
+        transfers.push_back(amount);
+        env.storage()
+            .instance()
+            .set(&MockKey::Transfers, &transfers);
+
+        let watch: Option<(Address, u32, Address)> = env.storage().instance().get(&MockKey::Watch);
+        if let Some((streamer, stream_id, recipient)) = watch {
+            if to == recipient {
+                let stored: Stream = env.as_contract(&streamer, || {
+                    env.storage()
+                        .persistent()
+                        .get(&DataKey::StreamKey(stream_id))
+                        .unwrap()
+                });
+                let withdrawn = stored.recipient_total_withdrawn.get(recipient).unwrap_or(0);
+                env.storage()
+                    .instance()
+                    .set(&MockKey::Seen, &(withdrawn, stored.is_active));
+            }
+        }
+    }
+
+    pub fn transfers(env: Env) -> Vec<i128> {
+        env.storage()
+            .instance()
+            .get(&MockKey::Transfers)
+            .unwrap_or_else(|| Vec::new(&env))
+    }
+
+    pub fn watch(env: Env, streamer: Address, stream_id: u32, recipient: Address) {
+        env.storage()
+            .instance()
+            .set(&MockKey::Watch, &(streamer, stream_id, recipient));
+    }
+
+    /// (recipient's total withdrawn, is_active) as stored when the last payout arrived
+    pub fn seen(env: Env) -> Option<(i128, bool)> {
+        env.storage().instance().get(&MockKey::Seen)
+    }
+}
+
+#[test]
+fn create_stream_returns_typed_errors() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    assert_eq!(
+        x.c.try_create_stream(
+            &s,
+            &vec![&x.env],
+            &x.tok,
+            &vec![&x.env],
+            &100,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::InvalidParameters))
+    );
+    assert_eq!(
+        x.c.try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 0i128],
+            &100,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::InvalidParameters))
+    );
+    assert_eq!(
+        x.c.try_create_stream(
+            &s,
+            &vec![&x.env, r.clone(), r.clone()],
+            &x.tok,
+            &vec![&x.env, 1i128, 1i128],
+            &1,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::DuplicateRecipient))
+    );
+    assert_eq!(
+        x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 100i128],
+            &100,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        1
+    );
+}
+
+#[test]
+fn withdraw_returns_typed_errors() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    assert_eq!(
+        x.c.try_withdraw_stream(&99, &r),
+        Err(Ok(Error::StreamNotFound))
+    );
+    let stranger = Address::generate(&x.env);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &stranger),
+        Err(Ok(Error::NotARecipient))
+    );
+}
+
+#[test]
+fn top_up_extends_deposit() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 50);
+    assert_eq!(
+        x.c.try_top_up_stream(&id, &0),
+        Err(Ok(Error::InvalidParameters))
+    );
+    assert_eq!(x.c.top_up_stream(&id, &500), 1_500);
+    adv(&x, 100);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 1_500);
+    assert_eq!(
+        x.c.try_top_up_stream(&id, &500),
+        Err(Ok(Error::StreamInactive))
+    );
+    assert_eq!(bal(&x, &s), 10_000 - 1_500);
+}
+
+#[test]
+fn pause_stops_accrual() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    x.c.pause_stream(&id);
+    adv(&x, 50);
+    assert_eq!(x.c.get_recipient_info(&id, &r).1, 100);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+    x.c.resume_stream(&id);
+    adv(&x, 5);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 50);
+}
+
+#[test]
+fn scheduled_start() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    assert!(x
+        .c
+        .try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(Some(999), None, None)
+        )
+        .is_err());
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(Some(1100), None, None),
+    );
+    adv(&x, 50);
+    assert_eq!(x.c.get_recipient_info(&id, &r).1, 0);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    adv(&x, 60);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+}
+
+#[test]
+fn end_time_caps_accrual() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &100_000,
+        &None,
+        &None,
+        &opts(None, Some(1020), None),
+    );
+    adv(&x, 50);
+    assert_eq!(x.c.get_recipient_info(&id, &r).1, 200);
+    x.c.cancel_stream(&id);
+    assert_eq!(bal(&x, &s), 1_000_000 - 200);
+}
+
+#[test]
+fn recipient_allocations_are_isolated() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    // rate 10 each, deposit 2000 -> each allocated 1000 -> 100s runway
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 1000],
+        &100,
+        &2000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    for _ in 0..12 {
+        adv(&x, 10);
+        let _ = x.c.try_withdraw_stream(&id, &b);
+    }
+    assert_eq!(bal(&x, &b), 1000);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 1000);
+    assert!(!x.c.get_stream(&id).is_active);
+}
+
+#[test]
+fn rate_division_dust_accrues() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &30,
+        &100,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    for _ in 0..6 {
+        adv(&x, 7);
+        let _ = x.c.try_withdraw_stream(&id, &a);
+    }
+    assert_eq!(bal(&x, &a), 100);
+}
+
+#[test]
+fn withdraw_to_destination() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let d = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream_to(&id, &r, &d), 100);
+    assert_eq!(bal(&x, &d), 100);
+    assert_eq!(bal(&x, &r), 0);
+    assert_eq!(x.c.get_recipient_info(&id, &r).0, 100);
+}
+
+#[test]
+fn withdraw_many_skips_failures() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let b = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 500i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    assert_eq!(
+        x.c.withdraw_many(&vec![&x.env, a, b, 99], &r),
+        vec![&x.env, (a, 100i128), (b, 50i128), (99u32, 0i128)]
+    );
+    assert_eq!(
+        x.c.withdraw_many(&vec![&x.env, a], &r),
+        vec![&x.env, (a, 0i128)]
+    );
+    assert_eq!(bal(&x, &r), 150);
+}
+
+#[test]
+fn partial_withdrawal() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &30,
+        &1000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 30);
+    assert_eq!(x.c.withdraw_stream_amount(&id, &a, &37), 37);
+    assert!(x.c.try_withdraw_stream_amount(&id, &a, &64).is_err());
+    assert_eq!(x.c.get_recipient_info(&id, &a).1, 63);
+    adv(&x, 30);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 163);
+    adv(&x, 300);
+    assert_eq!(x.c.withdraw_stream_amount(&id, &a, &500), 500);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 300);
+    assert!(!x.c.get_stream(&id).is_active);
+}
+
+#[test]
+fn stream_fee() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let col = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    assert!(x.c.try_set_stream_fee(&1001, &Some(col.clone())).is_err());
+    x.c.set_stream_fee(&250, &Some(col.clone()));
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &30,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(bal(&x, &col), 250);
+    assert_eq!(x.c.get_stream(&id).deposit, 9750);
+}
+
+#[test]
+fn storage_ttl_is_extended() {
+    use soroban_sdk::testutils::storage::Persistent as _;
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &30,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let ttl = x.env.as_contract(&x.c.address, || {
+        x.env
+            .storage()
+            .persistent()
+            .get_ttl(&DataKey::StreamKey(id))
+    });
+    assert!(ttl >= 518_000, "{}", ttl);
+}
+
+#[test]
+fn paged_stream_queries() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    for _ in 0..3 {
+        x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 100i128],
+            &100,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None),
+        );
+    }
+    let page = x.c.get_user_sent_streams_paged(&s, &1, &1);
+    assert_eq!(page.len(), 1);
+    assert_eq!(page.get(0).unwrap().id, 2);
+    assert_eq!(x.c.get_user_received_streams_paged(&r, &1, &100).len(), 2);
+    assert_eq!(x.c.get_user_sent_streams_paged(&s, &3, &10).len(), 0);
+    assert_eq!(x.c.get_user_sent_streams(&s).len(), 3);
+}
+
+#[test]
+fn stream_remaining_deposit() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128, 300],
+        &10,
+        &4000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    // 40/s total, 4000 -> 100s
+    adv(&x, 25);
+    x.c.withdraw_stream(&id, &a);
+    assert_eq!(x.c.get_stream_remaining(&id), (3000, 1100));
+}
+
+#[test]
+fn add_recipient() {
+    use soroban_sdk::testutils::Events;
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &10,
+        &2000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 50);
+    x.c.add_recipient(&id, &b, &100, &10);
+    // the event carries the rate accrual actually runs at, scaled like the flow-rate getters
+    let (_, _, d) = x.env.events().all().last().unwrap();
+    let v: soroban_sdk::Vec<soroban_sdk::Val> = soroban_sdk::FromVal::from_val(&x.env, &d);
+    let rate: i128 = soroban_sdk::FromVal::from_val(&x.env, &v.get(1).unwrap());
+    assert_eq!(rate, 10 * S);
+    assert_eq!(x.c.get_recipient_info(&id, &a).1, 500);
+    adv(&x, 100);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 1250);
+    assert_eq!(x.c.withdraw_stream(&id, &b), 750);
+    assert!(!x.c.get_stream(&id).is_active);
+    assert_eq!(x.c.get_user_received_stream_ids(&b).len(), 1);
+    // split streams share one weighted total, so a newcomer can't be bolted on
+    let split = x.c.create_split_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &1000,
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(
+        x.c.try_add_recipient(&split, &b, &100, &10),
+        Err(Ok(Error::InvalidParameters))
+    );
+}
+
+#[test]
+fn remove_recipient() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    let c = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone(), c.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128, 100, 100],
+        &10,
+        &3000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 30);
+    assert_eq!(x.c.remove_recipient(&id, &b), 300);
+    assert_eq!(x.c.get_recipient_info(&id, &a).1, 300);
+    adv(&x, 10);
+    assert_eq!(x.c.get_recipient_info(&id, &c).1, 400);
+    adv(&x, 200);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 1350);
+    assert_eq!(x.c.withdraw_stream(&id, &c), 1350);
+    assert_eq!(x.c.get_user_received_stream_ids(&b).len(), 0);
+    let id2 = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &10,
+        &3000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    let before = bal(&x, &s);
+    x.c.remove_recipient(&id2, &a);
+    assert_eq!(bal(&x, &s) - before, 2900);
+    assert!(!x.c.get_stream(&id2).is_active);
+}
+
+#[test]
+fn update_rate() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 100i128],
+        &10,
+        &100_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 30);
+    x.c.update_recipient_rate(&id, &a, &500, &10);
+    assert_eq!(bal(&x, &a), 300);
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 500);
+}
+
+#[test]
+fn two_step_admin_transfer() {
+    let x = setup();
+    let n = Address::generate(&x.env);
+    let stored_admin = || {
+        x.env.as_contract(&x.c.address, || {
+            x.env
+                .storage()
+                .persistent()
+                .get::<_, Address>(&DataKey::PlatformAdmin)
+                .unwrap()
+        })
+    };
+    assert_eq!(x.c.try_accept_admin(), Err(Ok(Error::NoPendingAdmin)));
+    x.c.propose_admin(&n);
+    x.c.cancel_admin_proposal();
+    assert_eq!(x.c.try_accept_admin(), Err(Ok(Error::NoPendingAdmin)));
+    x.c.propose_admin(&n);
+    assert_eq!(stored_admin(), x.admin);
+    x.c.accept_admin();
+    assert_eq!(stored_admin(), n);
+    assert_eq!(x.c.try_accept_admin(), Err(Ok(Error::NoPendingAdmin)));
+}
+
+#[test]
+fn sub_partial() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &1000, &None);
+    x.c.deposit_to_subscription(&id, &250);
+    adv(&x, 35);
+    assert_eq!(x.c.charge_subscription(&id), 2);
+    assert_eq!(bal(&x, &m), 200);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1020);
+    assert_eq!(x.c.charge_subscription(&id), 0);
+}
+
+#[test]
+fn sub_grace() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &20, &None);
+    adv(&x, 15);
+    assert_eq!(x.c.charge_subscription(&id), 0);
+    assert!(x.c.get_subscription(&id).active);
+    adv(&x, 10);
+    assert_eq!(x.c.charge_subscription(&id), 0);
+    assert!(!x.c.get_subscription(&id).active);
+}
+
+#[test]
+fn paused_subscription_is_not_charged() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &100, &1100, &None, &None, &0, &None);
+    x.c.deposit_to_subscription(&id, &1_000);
+    x.c.pause_subscription(&id);
+    assert_eq!(
+        x.c.try_pause_subscription(&id),
+        Err(Ok(Error::SubscriptionPaused))
+    );
+    adv(&x, 300);
+    assert_eq!(
+        x.c.try_charge_subscription(&id),
+        Err(Ok(Error::SubscriptionInactive))
+    );
+    assert_eq!(x.c.get_subscription(&id).balance, 1_000);
+    x.c.resume_subscription(&id);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1400);
+    assert_eq!(x.c.try_charge_subscription(&id), Err(Ok(Error::NotDueYet)));
+    assert_eq!(bal(&x, &m), 0);
+}
+
+#[test]
+fn sub_update_terms() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id = mksub(&x, &u, &m, 100, 10, 1000);
+    x.c.deposit_to_subscription(&id, &1000);
+    adv(&x, 5);
+    x.c.update_subscription_amount(&id, &50);
+    assert_eq!(bal(&x, &m), 100);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1010);
+    x.c.update_subscription_interval(&id, &20);
+    adv(&x, 10);
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    assert_eq!(bal(&x, &m), 150);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1030);
+    // a backlog the balance can't cover is never re-priced at the new terms; the partial
+    // charge is undone along with the rejected update
+    let short = x.c.create_subscription(
+        &u, &m, &x.tok, &100, &10, &1015, &None, &None, &1_000, &None,
+    );
+    x.c.deposit_to_subscription(&short, &150);
+    adv(&x, 20); // 1035: 1015, 1025, 1035 due
+    assert_eq!(
+        x.c.try_update_subscription_interval(&short, &1_000),
+        Err(Ok(Error::InsufficientContractBalance))
+    );
+    assert_eq!(
+        x.c.try_update_subscription_amount(&short, &1),
+        Err(Ok(Error::InsufficientContractBalance))
+    );
+    let sub = x.c.get_subscription(&short);
+    assert_eq!((sub.balance, sub.next_payment_time), (150, 1015));
+    assert_eq!(sub.interval_seconds, 10);
+}
+
+#[test]
+fn due_subs() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let a = mksub(&x, &u, &m, 100, 10, 1000);
+    let b = mksub(&x, &u, &m, 100, 10, 2000);
+    let c = mksub(&x, &u, &m, 100, 10, 1000);
+    x.c.deposit_to_subscription(&a, &100);
+    x.c.deposit_to_subscription(&b, &100);
+    let d = x.c.get_due_subscriptions(&0, &10);
+    assert_eq!(d.len(), 1);
+    assert_eq!(d.get(0).unwrap(), a);
+    let _ = c;
+}
+
+#[test]
+fn batch_charge() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let a = mksub(&x, &u, &m, 100, 10, 1000);
+    let b = mksub(&x, &u, &m, 100, 10, 2000);
+    let c = mksub(&x, &u, &m, 100, 10, 1000);
+    x.c.deposit_to_subscription(&a, &300);
+    x.c.deposit_to_subscription(&b, &100);
+    adv(&x, 15);
+    let mut ids = soroban_sdk::Vec::new(&x.env);
+    ids.push_back(a);
+    ids.push_back(b);
+    ids.push_back(c);
+    ids.push_back(99);
+    let r = x.c.charge_subscriptions(&ids);
+    assert_eq!(r.get(0).unwrap(), (a, 200));
+    assert_eq!(r.get(1).unwrap(), (b, 0));
+    assert_eq!(r.get(2).unwrap(), (c, 0));
+    assert_eq!(r.get(3).unwrap(), (99, 0));
+    assert_eq!(bal(&x, &m), 200);
+}
+
+#[test]
+fn admin_cancel() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 100);
+    x.c.withdraw_stream(&id, &r);
+    assert_eq!(x.c.admin_cancel_stream(&id), 9000);
+    assert_eq!(bal(&x, &s), 9000);
+    assert!(!x.c.get_stream(&id).is_active);
+    assert!(x.c.try_admin_cancel_stream(&id).is_err());
+}
+
+#[test]
+fn allow_list() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    // empty list: permissionless
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let other = Address::generate(&x.env);
+    x.c.allow_token(&other);
+    assert_eq!(
+        x.c.try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::TokenNotAllowed))
+    );
+    assert!(x
+        .c
+        .try_create_subscription(&s, &r, &x.tok, &100, &10, &1000, &None, &None, &0, &None)
+        .is_err());
+    x.c.allow_token(&x.tok);
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    mksub(&x, &s, &r, 100, 10, 1000);
+    x.c.disallow_token(&x.tok);
+    x.c.disallow_token(&other);
+    assert_eq!(x.c.get_allowed_tokens().len(), 0);
+    mksub(&x, &s, &r, 100, 10, 1000);
+}
+
+#[test]
+fn cliff_holds_back_accrual() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    assert!(x
+        .c
+        .try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(Some(1100), None, Some(1050))
+        )
+        .is_err());
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, Some(1500)),
+    );
+    adv(&x, 400);
+    assert!(x.c.try_withdraw_stream(&id, &r).is_err());
+    assert_eq!(x.c.get_recipient_info(&id, &r).1, 0);
+    adv(&x, 100);
+    assert_eq!(x.c.get_recipient_info(&id, &r).1, 5000);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 5000);
+}
+
+#[test]
+fn cliff_cancel_refunds_all() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, Some(1500)),
+    );
+    adv(&x, 400);
+    x.c.cancel_stream(&id);
+    assert_eq!(bal(&x, &s), 10_000);
+}
+
+#[test]
+fn done_event_once() {
+    use soroban_sdk::testutils::Events;
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 500);
+    x.c.withdraw_stream(&id, &r);
+    let evs = x.env.events().all();
+    let n = evs.len();
+    assert!(n >= 2);
+    assert!(!x.c.get_stream(&id).is_active);
+    assert!(x.c.try_withdraw_stream(&id, &r).is_err());
+}
+
+#[test]
+fn sub_status() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id = mksub(&x, &u, &m, 100, 10, 1000);
+    x.c.deposit_to_subscription(&id, &150);
+    adv(&x, 25);
+    assert_eq!(x.c.get_subscription_status(&id), (true, 3, 300, 150, 1000));
+}
+
+#[test]
+fn sweep_all() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    for _ in 0..3 {
+        x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(None, None, None),
+        );
+    }
+    adv(&x, 50);
+    assert_eq!(x.c.withdraw_all_received(&r), (1500, false));
+    assert_eq!(bal(&x, &r), 1500);
+    assert_eq!(x.c.withdraw_all_received(&r), (0, false));
+}
+
+#[test]
+fn sub_funded() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000);
+    let id =
+        x.c.create_subscription_funded(&u, &m, &x.tok, &100, &10, &1010, &None, &None, &0, &300);
+    assert_eq!(x.c.get_subscription(&id).balance, 300);
+    assert_eq!(bal(&x, &u), 700);
+    adv(&x, 10);
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    assert_eq!(bal(&x, &m), 100);
+}
+
+#[test]
+fn migrate_converts_legacy_records() {
+    use soroban_sdk::{Map, String};
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let e = &x.env;
+    let rates = Map::from_array(e, [(r.clone(), 2i128)]);
+    let v0 = StreamV0 {
+        id: 1,
+        sender: s.clone(),
+        recipients: vec![e, r.clone()],
+        token_contract: x.tok.clone(),
+        recipient_rate_per_second: rates.clone(),
+        deposit: 1_000,
+        start_time: 1000,
+        recipient_last_withdraw: Map::from_array(e, [(r.clone(), 1000u64)]),
+        recipient_total_withdrawn: Map::from_array(e, [(r.clone(), 0i128)]),
+        is_active: true,
+        title: None,
+        description: None,
+    };
+    let v1 = StreamV1 {
+        id: 2,
+        sender: s.clone(),
+        recipients: vec![e, r.clone()],
+        token_contract: x.tok.clone(),
+        recipient_rate_per_second: rates.clone(),
+        recipient_amount_per_period: Map::from_array(e, [(r.clone(), 300i128)]),
+        period_seconds: 100,
+        recipient_accrual_remainder: Map::new(e),
+        deposit: 600,
+        start_time: 1000,
+        recipient_last_withdraw: Map::from_array(e, [(r.clone(), 1000u64)]),
+        recipient_total_withdrawn: Map::from_array(e, [(r.clone(), 0i128)]),
+        recipient_allocation: Map::from_array(e, [(r.clone(), 600i128)]),
+        is_active: true,
+        title: Some(String::from_str(e, "v1")),
+        description: None,
+        paused_at: None,
+        end_time: None,
+        cliff_time: None,
+    };
+    let sub = SubscriptionV0 {
+        id: 1,
+        subscriber: s.clone(),
+        receiver: r.clone(),
+        token_contract: x.tok.clone(),
+        amount_per_interval: 10,
+        interval_seconds: 100,
+        next_payment_time: 1100,
+        active: true,
+        balance: 50,
+        title: None,
+        description: None,
+    };
+    e.as_contract(&x.c.address, || {
+        let st = e.storage().persistent();
+        st.set(&DataKey::Version, &1u32);
+        st.set(&DataKey::StreamKey(1), &v0);
+        st.set(&DataKey::StreamKey(2), &v1);
+        st.set(&DataKey::NextStreamId, &40u32);
+        st.set(&DataKey::SubscriptionKey(1), &sub);
+        st.set(&DataKey::NextSubscriptionId, &2u32);
+    });
+    mint(&x, &x.c.address, 1_650);
+    // 39 stream IDs and 1 subscription ID: two batches
+    assert_eq!(x.c.migrate(), 1);
+    assert_eq!(x.c.migrate(), 2);
+    assert_eq!(x.c.migrate(), 2);
+    assert_eq!(x.c.get_global_stats(), (39, 2, 1, 1));
+
+    let m0 = x.c.get_stream(&1);
+    assert_eq!(m0.period_seconds, 1);
+    assert_eq!(m0.recipient_allocation.get(r.clone()), Some(1_000));
+    assert_eq!(x.c.get_stream(&2).title, Some(String::from_str(e, "v1")));
+    assert_eq!(x.c.get_subscription(&1).billing_anchor, Some(1100));
+
+    adv(&x, 100);
+    assert_eq!(x.c.withdraw_stream(&1, &r), 200);
+    assert_eq!(x.c.withdraw_stream(&2, &r), 300);
+    assert_eq!(x.c.charge_subscription(&1), 1);
+    assert_eq!(bal(&x, &r), 510);
+}
+
+#[test]
+fn payout_sees_stored_withdrawal() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let tok = x.env.register(MockToken, ());
+    let mock = MockTokenClient::new(&x.env, &tok);
+    mock.mint(&s, &10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    mock.watch(&x.c.address, &id, &r);
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+    // By the time the token moves the funds the withdrawal is already on record
+    assert_eq!(mock.seen(), Some((100, true)));
+    assert_eq!(mock.balance(&r), 100);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    // Same for a cancellation: the settled stream is stored closed before anyone is paid
+    adv(&x, 10);
+    x.c.cancel_stream(&id);
+    assert_eq!(mock.seen(), Some((200, false)));
+    assert_eq!(mock.balance(&r), 200);
+    assert_eq!(mock.balance(&s), 9_800);
+    assert_eq!(
+        mock.transfers(),
+        vec![&x.env, 1_000i128, 100i128, 100i128, 800i128]
+    );
+}
+
+#[test]
+fn overflow_is_typed() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, i128::MAX / 2],
+        &1,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 1_000_000);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::ArithmeticOverflow))
+    );
+}
+
+#[test]
+fn reconcile_token_balance() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &5_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let sub = mksub(&x, &s, &r, 100, 10, 1000);
+    x.c.deposit_to_subscription(&sub, &700);
+    adv(&x, 30);
+    x.c.withdraw_stream(&a, &r);
+    x.c.charge_subscription(&sub);
+    assert_eq!(
+        x.c.get_total_escrowed(&x.tok),
+        x.c.get_contract_token_balance(&x.tok)
+    );
+    assert_eq!(
+        x.c.get_total_escrowed(&x.tok),
+        10_000 - 300 + 5_000 + 700 - 400
+    );
+}
+
+#[test]
+fn cancel_notice() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let mut o = opts(None, None, None);
+    o.cancel_notice_seconds = Some(100);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &o,
+    );
+    adv(&x, 50);
+    x.c.cancel_stream(&id);
+    assert!(x.c.get_stream(&id).is_active);
+    assert!(x.c.try_cancel_stream(&id).is_err());
+    adv(&x, 200);
+    assert_eq!(x.c.get_recipient_info(&id, &r).1, 1500);
+    x.c.cancel_stream(&id);
+    assert_eq!(bal(&x, &s), 8500);
+    assert!(!x.c.get_stream(&id).is_active);
+}
+
+#[test]
+fn streams_by_token() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let sac2 = x.env.register_stellar_asset_contract_v2(x.admin.clone());
+    let tok2 = sac2.address();
+    token::StellarAssetClient::new(&x.env, &tok2).mint(&s, &100_000);
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &tok2,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let c = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let v = x.c.get_user_streams_by_token(&r, &x.tok, &0, &10);
+    assert_eq!(v.len(), 2);
+    assert_eq!(v.get(0).unwrap().id, a);
+    assert_eq!(v.get(1).unwrap().id, c);
+    assert_eq!(x.c.get_user_streams_by_token(&s, &tok2, &0, &10).len(), 1);
+    assert_eq!(x.c.get_user_streams_by_token(&s, &x.tok, &1, &1).len(), 0);
+}
+
+#[test]
+fn min_floors() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    x.c.set_min_deposit(&5_000);
+    x.c.set_min_period_seconds(&60);
+    assert!(x
+        .c
+        .try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &4_999,
+            &None,
+            &None,
+            &opts(None, None, None)
+        )
+        .is_err());
+    assert!(x
+        .c
+        .try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &10,
+            &10_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        )
+        .is_err());
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &5_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+}
+
+#[test]
+fn total_rate() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 2000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let c = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 500i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(x.c.get_recipient_total_rate(&r, &x.tok), 35);
+    assert_eq!(x.c.get_recipient_total_rate_scaled(&r, &x.tok), 35 * S);
+    x.c.pause_stream(&c);
+    assert_eq!(x.c.get_recipient_total_rate(&r, &x.tok), 30);
+}
+
+#[test]
+fn reclaim_stale() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000);
+    let id = mksub(&x, &u, &m, 100, 10, 1010);
+    x.c.deposit_to_subscription(&id, &500);
+    x.c.set_stale_seconds(&100);
+    adv(&x, 100);
+    assert!(x.c.try_reclaim_stale_subscription(&id).is_err());
+    adv(&x, 20);
+    assert_eq!(x.c.reclaim_stale_subscription(&id), 500);
+    assert_eq!(bal(&x, &u), 1_000);
+    let sub = x.c.get_subscription(&id);
+    assert!(sub.active);
+    assert_eq!(sub.balance, 0);
+    assert_eq!(
+        x.c.try_reclaim_stale_subscription(&id),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+}
+
+#[test]
+fn split_stream() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    let c = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_split_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone(), c.clone()],
+        &x.tok,
+        &1000,
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let st = x.c.get_stream(&id);
+    assert_eq!(st.recipient_amount_per_period.get(a.clone()).unwrap(), 334);
+    assert_eq!(st.recipient_amount_per_period.get(b.clone()).unwrap(), 333);
+    let id2 = x.c.create_split_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone()],
+        &x.tok,
+        &1001,
+        &100,
+        &10_000,
+        &Some(vec![&x.env, 1u32, 3u32]),
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let st = x.c.get_stream(&id2);
+    assert_eq!(st.recipient_amount_per_period.get(a.clone()).unwrap(), 251);
+    assert_eq!(st.recipient_amount_per_period.get(b.clone()).unwrap(), 750);
+    assert!(x
+        .c
+        .try_create_split_stream(
+            &s,
+            &vec![&x.env, a.clone(), b.clone()],
+            &x.tok,
+            &1000,
+            &100,
+            &10_000,
+            &Some(vec![&x.env, 1u32]),
+            &None,
+            &None,
+            &opts(None, None, None)
+        )
+        .is_err());
+    assert!(x
+        .c
+        .try_create_split_stream(
+            &s,
+            &vec![&x.env, a.clone(), b.clone()],
+            &x.tok,
+            &1000,
+            &100,
+            &10_000,
+            &Some(vec![&x.env, 1u32, 0u32]),
+            &None,
+            &None,
+            &opts(None, None, None)
+        )
+        .is_err());
+}
+
+#[test]
+fn stream_view() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 500i128],
+        &100,
+        &3_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 100);
+    let v = x.c.get_stream_view(&id);
+    assert_eq!(v.remaining_deposit, 1500);
+    assert_eq!(v.total_outflow_rate, 15 * S);
+    assert!(!v.is_exhausted);
+    adv(&x, 200);
+    let v = x.c.get_stream_view(&id);
+    assert_eq!(v.remaining_deposit, 0);
+    assert_eq!(v.total_outflow_rate, 0);
+    assert!(v.is_exhausted);
+}
+
+#[test]
+fn zero_withdraw_same_ledger() {
+    use soroban_sdk::testutils::Events;
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    x.c.withdraw_stream(&id, &r);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    // failed invocation emits no transfer event
+    assert_eq!(x.env.events().all().len(), 0);
+}
+
+#[test]
+fn zero_amount_settlements_skip_transfer() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    let tok = x.env.register(MockToken, ());
+    let mock = MockTokenClient::new(&x.env, &tok);
+    mock.mint(&s, &10_000);
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &tok,
+        &vec![&x.env, 1000i128, 1000i128],
+        &100,
+        &2_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let b = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone()],
+        &tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    // Nothing has accrued yet: removing r2 settles 0 and must not call the token
+    assert_eq!(x.c.remove_recipient(&a, &r2), 0);
+    assert_eq!(mock.transfers(), vec![&x.env, 2_000i128, 1_000i128]);
+    // Sweeping r1 pays only the stream with something accrued
+    adv(&x, 10);
+    x.c.withdraw_stream(&b, &r1);
+    assert_eq!(x.c.withdraw_all_received(&r1), (100, false));
+    assert_eq!(
+        mock.transfers(),
+        vec![&x.env, 2_000i128, 1_000i128, 100i128, 100i128]
+    );
+    assert!(!mock.transfers().contains(0i128));
+}
+
+#[test]
+fn rotate_recipient_key() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let n = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    x.c.rotate_recipient(&id, &r, &n);
+    assert_eq!(bal(&x, &r), 100);
+    adv(&x, 20);
+    assert!(x.c.try_withdraw_stream(&id, &r).is_err());
+    assert_eq!(x.c.withdraw_stream(&id, &n), 200);
+    assert_eq!(x.c.get_user_received_stream_ids(&r).len(), 0);
+    assert_eq!(x.c.get_user_received_stream_ids(&n).len(), 1);
+}
+
+#[test]
+fn sub_fee() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    let col = Address::generate(&x.env);
+    mint(&x, &u, 1_000);
+    x.c.set_subscription_fee(&250, &Some(col.clone()));
+    let id = mksub(&x, &u, &m, 100, 10, 1000);
+    x.c.deposit_to_subscription(&id, &500);
+    x.c.charge_subscription(&id);
+    assert_eq!(bal(&x, &m), 98);
+    assert_eq!(bal(&x, &col), 2);
+    assert_eq!(x.c.get_subscription(&id).balance, 400);
+}
+
+#[test]
+fn all_ids_sparse() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    for _ in 0..4 {
+        x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(None, None, None),
+        );
+    }
+    x.env.as_contract(&x.c.address, || {
+        x.env.storage().persistent().remove(&DataKey::StreamKey(2));
+    });
+    let ids = x.c.get_all_stream_ids(&0, &10);
+    assert_eq!(ids, vec![&x.env, 1u32, 3, 4]);
+    assert_eq!(x.c.get_all_stream_ids(&3, &1), vec![&x.env, 3u32]);
+    mksub(&x, &s, &r, 100, 10, 1000);
+    assert_eq!(x.c.get_all_subscription_ids(&1, &5), vec![&x.env, 1u32]);
+}
+
+#[test]
+fn prune_exhausted_stream() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let o = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(
+        x.c.try_prune_stream(&id, &s),
+        Err(Ok(Error::StreamStillActive))
+    );
+    x.c.cancel_stream(&id);
+    assert_eq!(x.c.try_prune_stream(&id, &o), Err(Ok(Error::Unauthorized)));
+    x.c.prune_stream(&id, &s);
+    assert_eq!(x.c.get_user_sent_stream_ids(&s).len(), 0);
+    assert_eq!(x.c.get_user_received_stream_ids(&r).len(), 0);
+    x.c.get_stream(&id);
+    // a stream still waiting to be accepted is inactive but holds its deposit
+    let mut o = opts(None, None, None);
+    o.require_acceptance = true;
+    let pending = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &o,
+    );
+    assert!(!x.c.get_stream(&pending).is_active);
+    assert_eq!(
+        x.c.try_prune_stream(&pending, &s),
+        Err(Ok(Error::StreamStillActive))
+    );
+    assert_eq!(x.c.get_user_sent_stream_ids(&s).len(), 1);
+}
+
+#[test]
+fn sponsor_deposit() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    let p = Address::generate(&x.env);
+    mint(&x, &p, 1_000);
+    let id = mksub(&x, &u, &m, 100, 10, 1000);
+    x.c.deposit_to_subscription_from(&id, &p, &300);
+    assert_eq!(bal(&x, &p), 700);
+    assert_eq!(x.c.get_subscription(&id).balance, 300);
+    x.c.cancel_subscription(&id);
+    assert_eq!(bal(&x, &u), 300);
+}
+
+#[test]
+fn preview_matches_withdraw() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone()],
+        &x.tok,
+        &vec![&x.env, 333i128, 500i128],
+        &100,
+        &2_000,
+        &None,
+        &None,
+        &opts(None, None, Some(1005)),
+    );
+    for step in [3u64, 7, 13, 100, 500] {
+        adv(&x, step);
+        let p = x.c.preview_withdraw(&id, &a);
+        let got = if p == 0 {
+            assert!(x.c.try_withdraw_stream(&id, &a).is_err());
+            0
+        } else {
+            x.c.withdraw_stream(&id, &a)
+        };
+        assert_eq!(p, got);
+    }
+}
+
+#[test]
+fn weighted_split_updates() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let a = Address::generate(&x.env);
+    let b = Address::generate(&x.env);
+    let c = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_split_stream(
+        &s,
+        &vec![&x.env, a.clone(), b.clone(), c.clone()],
+        &x.tok,
+        &6000,
+        &100,
+        &60_000,
+        &Some(vec![&x.env, 1u32, 2u32, 3u32]),
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.preview_withdraw(&id, &a), 100);
+    assert_eq!(x.c.preview_withdraw(&id, &b), 200);
+    assert_eq!(x.c.preview_withdraw(&id, &c), 300);
+    x.c.update_recipient_weight(&id, &a, &4); // 4:2:3 of 60/s
+    adv(&x, 9);
+    assert_eq!(x.c.withdraw_stream(&id, &a), 100 + 240); // 2667/100 per s
+    assert_eq!(x.c.withdraw_stream(&id, &b), 200 + 119);
+    assert_eq!(x.c.withdraw_stream(&id, &c), 300 + 180);
+    let plain = x.c.create_stream(
+        &s,
+        &vec![&x.env, a.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert!(x.c.try_update_recipient_weight(&plain, &a, &2).is_err());
+}
+
+#[test]
+fn acceptance_mode() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let mut o = opts(None, None, None);
+    o.require_acceptance = true;
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &o,
+    );
+    assert!(!x.c.get_stream(&id).is_active);
+    adv(&x, 10);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NotAccepted))
+    );
+    x.c.accept_stream(&id, &r);
+    assert!(x.c.get_stream(&id).is_active);
+    assert_eq!(
+        x.c.try_accept_stream(&id, &r),
+        Err(Ok(Error::InvalidParameters))
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+    assert_eq!(x.c.reclaim_unaccepted(&id, &r2), 5_000);
+    assert_eq!(bal(&x, &s), 1_000_000 - 10_000 + 5_000);
+    assert_eq!(
+        x.c.try_reclaim_unaccepted(&id, &r),
+        Err(Ok(Error::InvalidParameters))
+    );
+}
+
+#[test]
+fn version_reported() {
+    let x = setup();
+    assert_eq!(x.c.version(), 2);
+}
+
+#[test]
+fn create_validates_before_transfer() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let res = x.c.try_create_stream(
+        &s,
+        &vec![&x.env, r.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 0i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(res, Err(Ok(Error::InvalidParameters)));
+    assert_eq!(bal(&x, &s), 1_000_000);
+}
+
+#[test]
+fn sub_view_shortfall() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &1000, &None);
+    x.c.deposit_to_subscription(&id, &250);
+    adv(&x, 25);
+    let v = x.c.get_subscription_view(&id);
+    assert_eq!(v.intervals_due, 3);
+    assert_eq!(v.amount_now_due, 300);
+    assert_eq!(v.shortfall, 50);
+}
+
+#[test]
+fn withdraw_cap() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+    assert_eq!(
+        x.c.try_set_recipient_withdraw_cap(&id, &r, &Some(50)),
+        Err(Ok(Error::InvalidParameters))
+    );
+    x.c.set_recipient_withdraw_cap(&id, &r, &Some(150));
+    adv(&x, 100);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 50);
+    adv(&x, 10);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    x.c.set_recipient_withdraw_cap(&id, &r, &None);
+    assert!(x.c.withdraw_stream(&id, &r) > 0);
+}
+
+#[test]
+fn collected_fees() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let col = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    x.c.set_stream_fee(&100, &Some(col.clone()));
+    for _ in 0..3 {
+        x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(None, None, None),
+        );
+    }
+    assert_eq!(x.c.get_collected_fees(&x.tok), 300);
+    assert_eq!(bal(&x, &col), 300);
+}
+
+#[test]
+fn native_stream() {
+    let x = setup();
+    let native = x
+        .env
+        .deployer()
+        .with_stellar_asset(soroban_sdk::Bytes::from_array(&x.env, &[0u8; 4]))
+        .deploy();
+    assert_eq!(x.c.get_native_token(), native);
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let res = x.c.try_create_native_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    // Test accounts hold no XLM, so the deposit transfer is where this stops
+    assert!(res.is_err());
+    assert_eq!(token::Client::new(&x.env, &native).decimals(), 7);
+}
+
+#[test]
+fn sub_catchup_cap() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &1000, &None);
+    x.c.deposit_to_subscription(&id, &10_000);
+    x.c.set_max_catchup_intervals(&id, &Some(3));
+    adv(&x, 500);
+    assert_eq!(x.c.charge_subscription(&id), 3);
+    assert_eq!(bal(&x, &m), 300);
+    let sub = x.c.get_subscription(&id);
+    assert_eq!(sub.next_payment_time, 1030);
+    assert!(sub.active);
+}
+
+#[test]
+fn sub_anchored_prorated() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    // boundaries every 100s from 50: signup at 1000 -> first boundary 1050, half an interval
+    let id = x
+        .c
+        .create_subscription_anchored(&u, &m, &x.tok, &1000, &100, &50, &true, &None, &None, &1000);
+    let sub = x.c.get_subscription(&id);
+    assert_eq!(sub.next_payment_time, 1050);
+    assert_eq!(sub.prorated_first_amount, Some(500));
+    x.c.deposit_to_subscription(&id, &10_000);
+    adv(&x, 160);
+    assert_eq!(x.c.get_subscription_view(&id).amount_now_due, 1500);
+    assert_eq!(
+        x.c.charge_subscriptions(&vec![&x.env, id]),
+        vec![&x.env, (id, 1500i128)]
+    );
+    assert_eq!(bal(&x, &m), 1500);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1250);
+    // future anchor
+    let id2 = x.c.create_subscription_anchored(
+        &u, &m, &x.tok, &1000, &100, &5000, &false, &None, &None, &1000,
+    );
+    assert_eq!(x.c.get_subscription(&id2).next_payment_time, 1200);
+    assert_eq!(x.c.get_subscription(&id2).prorated_first_amount, None);
+    x.c.pause_subscription(&id2);
+    adv(&x, 30);
+    x.c.resume_subscription(&id2);
+    assert_eq!(x.c.get_subscription(&id2).next_payment_time, 1300);
+}
+
+#[test]
+fn sub_transfer_receiver() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    let m2 = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &1000, &None);
+    x.c.deposit_to_subscription(&id, &1_000);
+    adv(&x, 5);
+    x.c.transfer_subscription_receiver(&id, &m2);
+    assert_eq!(bal(&x, &m), 100);
+    assert_eq!(x.c.get_user_received_subscriptions(&m).len(), 0);
+    assert_eq!(x.c.get_user_received_subscriptions(&m2).len(), 1);
+    adv(&x, 10);
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    assert_eq!(bal(&x, &m2), 100);
+    assert_eq!(x.c.get_subscription(&id).balance, 800);
+}
+
+#[test]
+fn cancel_all_sent() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    x.c.set_max_streams_per_ledger(&0);
+    let mut ids = std::vec::Vec::new();
+    for _ in 0..12 {
+        ids.push(x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &10_000,
+            &None,
+            &None,
+            &opts(None, None, None),
+        ));
+    }
+    adv(&x, 10);
+    x.c.withdraw_stream(&ids[0], &r);
+    let (refunded, more) = x.c.cancel_all_sent_streams(&s, &0, &10);
+    assert!(more);
+    assert_eq!(refunded, 10 * 9_900);
+    assert_eq!(
+        x.c.cancel_all_sent_streams(&s, &10, &10),
+        (2 * 9_900, false)
+    );
+    // going over the same page again finds nothing left to cancel
+    assert_eq!(x.c.cancel_all_sent_streams(&s, &0, &100), (0, false));
+    // each stream paid its 100 of accrual to the recipient, withdrawn or not
+    assert_eq!(bal(&x, &r), 12 * 100);
+    assert_eq!(bal(&x, &s), 1_000_000 - 12 * 100);
+}
+
+#[test]
+fn cancel_settles_unwithdrawn() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 500i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 20);
+    x.c.withdraw_stream(&id, &r2);
+    adv(&x, 10);
+    x.c.cancel_stream(&id);
+    assert_eq!(bal(&x, &r), 300);
+    assert_eq!(bal(&x, &r2), 150);
+    assert_eq!(bal(&x, &s), 1_000_000 - 450);
+    assert_eq!(
+        x.c.get_stream(&id).recipient_total_withdrawn.get(r.clone()),
+        Some(300)
+    );
+}
+
+#[test]
+fn decimals_scaling() {
+    let x = setup();
+    assert_eq!(x.c.get_token_decimals(&x.tok), 7);
+    let per_period = x.c.to_atomic_units(&x.tok, &30);
+    assert_eq!(per_period, 300_000_000);
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, per_period);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, per_period],
+        &30,
+        &per_period,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(
+        x.c.get_stream(&id).recipient_rate_per_second.get(r),
+        Some(10_000_000)
+    );
+    assert!(x.c.try_to_atomic_units(&x.tok, &i128::MAX).is_err());
+}
+
+#[test]
+fn sub_empty_event() {
+    use soroban_sdk::testutils::Events;
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &1000, &None);
+    x.c.deposit_to_subscription(&id, &200);
+    let empties = |x: &Ctx| {
+        std::format!("{:?}", x.env.events().all())
+            .matches("sub_empty")
+            .count()
+    };
+    x.c.charge_subscription(&id);
+    assert_eq!(empties(&x), 0);
+    adv(&x, 10);
+    x.c.charge_subscription(&id);
+    assert_eq!(empties(&x), 1);
+    adv(&x, 10);
+    x.c.charge_subscription(&id);
+    assert_eq!(empties(&x), 0);
+}
+
+#[test]
+fn preview_all() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 700i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 13);
+    x.c.withdraw_stream(&id, &r2);
+    adv(&x, 7);
+    let p = x.c.preview_all_withdrawals(&id);
+    assert_eq!(p.get(0).unwrap().1, x.c.withdraw_stream(&id, &r));
+    assert_eq!(p.get(1).unwrap().1, x.c.withdraw_stream(&id, &r2));
+}
+
+#[test]
+fn references_round_trip() {
+    use soroban_sdk::testutils::Events;
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let mut o = opts(None, None, None);
+    o.reference = Some(424242);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &o,
+    );
+    let last_ref = |x: &Ctx, idx: u32| {
+        let (_, _, d) = x.env.events().all().last().unwrap();
+        let v: soroban_sdk::Vec<soroban_sdk::Val> = soroban_sdk::FromVal::from_val(&x.env, &d);
+        let r: Option<u64> = soroban_sdk::FromVal::from_val(&x.env, &v.get(idx).unwrap());
+        r
+    };
+    assert_eq!(last_ref(&x, 6), Some(424242));
+    assert_eq!(x.c.get_stream(&id).reference, Some(424242));
+    let sid = x.c.create_subscription(
+        &s,
+        &r,
+        &x.tok,
+        &100,
+        &10,
+        &1000,
+        &None,
+        &None,
+        &0,
+        &Some(777_001),
+    );
+    assert_eq!(last_ref(&x, 7), Some(777_001));
+    assert_eq!(x.c.get_subscription(&sid).reference, Some(777_001));
+}
+
+#[test]
+fn fee_config_validation() {
+    let x = setup();
+    let col = Address::generate(&x.env);
+    assert_eq!(
+        x.c.try_set_stream_fee(&100, &None),
+        Err(Ok(Error::InvalidParameters))
+    );
+    x.c.set_stream_fee(&0, &None);
+    x.c.set_subscription_fee(&50, &Some(col.clone()));
+    x.c.set_stream_fee(&100, &None);
+    assert_eq!(x.c.get_fee_config(), (100, 50, Some(col.clone())));
+
+    let env = &x.env;
+    let id = env.register(Streamer, ());
+    let c2 = StreamerClient::new(env, &id);
+    c2.init(&x.admin, &None, &Some(col.clone()));
+    c2.set_stream_fee(&100, &None);
+    assert_eq!(c2.get_fee_config(), (100, 0, Some(col)));
+}
+
+#[test]
+fn streams_ending_before() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    // 10/s: lives of 100s, 1000s and 5000s
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let b = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let _c = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &50_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(
+        x.c.get_user_streams_ending_before(&s, &1500, &0, &10),
+        vec![&x.env, a]
+    );
+    assert_eq!(
+        x.c.get_user_streams_ending_before(&s, &3000, &0, &10),
+        vec![&x.env, a, b]
+    );
+    assert_eq!(
+        x.c.get_user_streams_ending_before(&s, &3000, &1, &1),
+        vec![&x.env, b]
+    );
+}
+
+#[test]
+fn sub_reactivate() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1000, &None, &None, &0, &None);
+    x.c.deposit_to_subscription(&id, &500);
+    assert_eq!(
+        x.c.try_reactivate_subscription(&id, &1100),
+        Err(Ok(Error::InvalidParameters))
+    );
+    x.c.cancel_subscription(&id);
+    assert_eq!(
+        x.c.try_reactivate_subscription(&id, &999),
+        Err(Ok(Error::InvalidParameters))
+    );
+    x.c.reactivate_subscription(&id, &1100);
+    x.c.deposit_to_subscription(&id, &300);
+    adv(&x, 100);
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    assert_eq!(bal(&x, &m), 100);
+    // lapsed ones stay closed
+    let id2 =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &10, &1100, &None, &None, &0, &None);
+    adv(&x, 5);
+    x.c.charge_subscription(&id2);
+    assert!(!x.c.get_subscription(&id2).active);
+    assert_eq!(
+        x.c.try_reactivate_subscription(&id2, &1200),
+        Err(Ok(Error::InvalidParameters))
+    );
+}
+
+#[test]
+fn creation_rate_limit() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    x.c.set_max_streams_per_ledger(&3);
+    for _ in 0..3 {
+        x.c.create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None),
+        );
+    }
+    assert_eq!(
+        x.c.try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 1000i128],
+            &100,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::RateLimited))
+    );
+    let seq = x.env.ledger().sequence();
+    x.env.ledger().set_sequence_number(seq + 1);
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+}
+
+#[test]
+fn recipient_active_ids() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000_000);
+    let live = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let done = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128],
+        &100,
+        &100,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let removed = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 1000i128, 1000i128],
+        &100,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 20);
+    x.c.withdraw_stream(&done, &r);
+    x.c.remove_recipient(&removed, &r);
+    assert_eq!(x.c.get_recipient_active_stream_ids(&r), vec![&x.env, live]);
+    assert_eq!(
+        x.c.get_recipient_active_stream_ids(&r2),
+        vec![&x.env, removed]
+    );
+}
+
+#[test]
+fn sub_pause_keeps_cadence() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 1_000_000);
+    let id =
+        x.c.create_subscription(&u, &m, &x.tok, &100, &100, &1100, &None, &None, &0, &None);
+    x.c.deposit_to_subscription(&id, &1_000);
+    adv(&x, 150);
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    x.c.pause_subscription(&id);
+    adv(&x, 30);
+    x.c.resume_subscription(&id);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1300);
+    adv(&x, 220);
+    assert_eq!(x.c.charge_subscription(&id), 2);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1500);
+}
+
+#[test]
+fn dust_sweep() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000);
+    // 1001 over rates 3:7 -> uneven split
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 3i128, 7i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(x.c.get_stream_dust(&id), 0);
+    assert_eq!(
+        x.c.try_sweep_stream_dust(&id),
+        Err(Ok(Error::StreamStillActive))
+    );
+    adv(&x, 100_000);
+    x.c.withdraw_stream(&id, &r1);
+    x.c.withdraw_stream(&id, &r2);
+    assert!(!x.c.get_stream(&id).is_active);
+    assert_eq!(bal(&x, &r1) + bal(&x, &r2), 1_000);
+    assert_eq!(x.c.get_stream_dust(&id), 0);
+    assert_eq!(
+        x.c.try_sweep_stream_dust(&id),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    // Simulate a stream exhausted under the old truncating logic: 3 units left allocated
+    let cid = x.c.address.clone();
+    mint(&x, &cid, 3);
+    x.env.as_contract(&cid, || {
+        let mut st: Stream = x
+            .env
+            .storage()
+            .persistent()
+            .get(&DataKey::StreamKey(id))
+            .unwrap();
+        let a = st.recipient_allocation.get(r2.clone()).unwrap();
+        st.recipient_allocation.set(r2.clone(), a + 3);
+        x.env
+            .storage()
+            .persistent()
+            .set(&DataKey::StreamKey(id), &st);
+    });
+    assert_eq!(x.c.get_stream_dust(&id), 3);
+    assert_eq!(x.c.sweep_stream_dust(&id), 3);
+    assert_eq!(bal(&x, &s), 3);
+    assert_eq!(x.c.get_stream_dust(&id), 0);
+    assert_eq!(
+        x.c.try_sweep_stream_dust(&id),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r2),
+        Err(Ok(Error::StreamInactive))
+    );
+}
+
+#[test]
+fn dust_cancelled_zero() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    x.c.cancel_stream(&id);
+    assert_eq!(x.c.get_stream_dust(&id), 0);
+    assert_eq!(
+        x.c.try_sweep_stream_dust(&id),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+}
+
+#[test]
+fn gated_release() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    let ap = Address::generate(&x.env);
+    let other = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let mut o = opts(None, None, None);
+    o.gate_approver = Some(ap.clone());
+    assert_eq!(
+        x.c.try_create_stream(
+            &s,
+            &vec![&x.env, r.clone()],
+            &x.tok,
+            &vec![&x.env, 10i128],
+            &1,
+            &1_000,
+            &None,
+            &None,
+            &o
+        ),
+        Err(Ok(Error::InvalidParameters))
+    );
+    o.gated = true;
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &o,
+    );
+    adv(&x, 50);
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    assert_eq!(
+        x.c.try_release_gate(&id, &other),
+        Err(Ok(Error::Unauthorized))
+    );
+    x.c.release_gate(&id, &ap);
+    assert_eq!(
+        x.c.try_release_gate(&id, &s),
+        Err(Ok(Error::InvalidParameters))
+    );
+    assert_eq!(
+        x.c.try_withdraw_stream(&id, &r),
+        Err(Ok(Error::NothingToWithdraw))
+    );
+    adv(&x, 5);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 50);
+    // cancel refunds the rest
+    x.c.cancel_stream(&id);
+    assert_eq!(bal(&x, &s), 10_000 - 50);
+}
+
+#[test]
+fn global_stats() {
+    let x = setup();
+    x.c.set_max_streams_per_ledger(&0);
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    assert_eq!(x.c.get_global_stats(), (0, 0, 0, 0));
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &100,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let b = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let mut o = opts(None, None, None);
+    o.require_acceptance = true;
+    let c = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &o,
+    );
+    assert_eq!(x.c.get_global_stats(), (3, 2, 0, 0));
+    x.c.accept_stream(&c, &r);
+    assert_eq!(x.c.get_global_stats(), (3, 3, 0, 0));
+    adv(&x, 20);
+    // exhaust a, then a second withdraw attempt must not decrement again
+    x.c.withdraw_stream(&a, &r);
+    assert!(x.c.try_withdraw_stream(&a, &r).is_err());
+    assert_eq!(x.c.get_global_stats(), (3, 2, 0, 0));
+    x.c.cancel_stream(&b);
+    assert_eq!(x.c.get_global_stats(), (3, 1, 0, 0));
+    let s1 = mksub(&x, &s, &r, 10, 100, 2000);
+    let _s2 = mksub(&x, &s, &r, 10, 100, 2000);
+    assert_eq!(x.c.get_global_stats(), (3, 1, 2, 2));
+    x.c.cancel_subscription(&s1);
+    assert_eq!(x.c.get_global_stats(), (3, 1, 2, 1));
+    x.c.reactivate_subscription(&s1, &3000);
+    assert_eq!(x.c.get_global_stats(), (3, 1, 2, 2));
+}
+
+#[test]
+fn batch_subscriptions() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let specs = vec![
+        &x.env,
+        (10i128, 100u64, 1100u64),
+        (20i128, 50u64, 1050u64),
+        (5i128, 10u64, 1010u64),
+    ];
+    let ids = x.c.create_subscriptions(&u, &m, &x.tok, &specs);
+    assert_eq!(ids.len(), 3);
+    assert_eq!(x.c.get_user_subscriptions(&u).len(), 3);
+    for id in ids.iter() {
+        x.c.deposit_to_subscription(&id, &100);
+    }
+    adv(&x, 100);
+    assert_eq!(x.c.charge_subscription(&ids.get(0).unwrap()), 1);
+    assert_eq!(x.c.charge_subscription(&ids.get(1).unwrap()), 2);
+    assert_eq!(x.c.charge_subscription(&ids.get(2).unwrap()), 10);
+    assert_eq!(bal(&x, &m), 10 + 40 + 50);
+    assert!(x
+        .c
+        .try_create_subscriptions(&u, &m, &x.tok, &vec![&x.env])
+        .is_err());
+}
+
+#[test]
+fn not_initialized() {
+    let env = Env::default();
+    env.mock_all_auths();
+    let admin = Address::generate(&env);
+    let tok = env
+        .register_stellar_asset_contract_v2(admin.clone())
+        .address();
+    let c = StreamerClient::new(&env, &env.register(Streamer, ()));
+    let s = Address::generate(&env);
+    let r = Address::generate(&env);
+    assert_eq!(
+        c.try_create_stream(
+            &s,
+            &vec![&env, r.clone()],
+            &tok,
+            &vec![&env, 10i128],
+            &1,
+            &100,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::NotInitialized))
+    );
+    assert_eq!(
+        c.try_create_subscription(&s, &r, &tok, &10, &10, &10, &None, &None, &0, &None),
+        Err(Ok(soroban_sdk::Error::from_contract_error(11)))
+    );
+    assert_eq!(
+        c.try_withdraw_stream(&1, &r),
+        Err(Ok(Error::NotInitialized))
+    );
+}
+
+#[test]
+fn subscription_totals() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id = mksub(&x, &u, &m, 10, 100, 1100);
+    x.c.deposit_to_subscription(&id, &1_000);
+    assert_eq!(x.c.get_subscription_totals(&id), (0, 0));
+    adv(&x, 100);
+    x.c.charge_subscription(&id);
+    adv(&x, 250);
+    assert_eq!(x.c.charge_subscription(&id), 2);
+    assert_eq!(x.c.get_subscription_totals(&id), (30, 3));
+    let sub = x.c.get_subscription(&id);
+    assert_eq!((sub.total_charged, sub.charge_count), (30, 3));
+}
+
+#[test]
+fn recipient_close_then_cancel() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 1_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.recipient_close(&id, &r1), 100);
+    assert_eq!(bal(&x, &r1), 100);
+    assert_eq!(
+        x.c.try_recipient_close(&id, &r1),
+        Err(Ok(Error::NotARecipient))
+    );
+    let st = x.c.get_stream(&id);
+    assert_eq!(st.recipients.len(), 1);
+    assert_eq!(x.c.get_user_received_stream_ids(&r1).len(), 0);
+    adv(&x, 10);
+    // freed allocation went to r2's pool; sender reclaims everything not accrued on cancel
+    x.c.cancel_stream(&id);
+    assert_eq!(bal(&x, &r2), 200);
+    assert_eq!(bal(&x, &s), 1_000 - 300);
+}
+
+#[test]
+fn periodic_subscriptions() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    let cases = [
+        (Period::Daily, 1u32, 86_400u64),
+        (Period::Weekly, 2, 1_209_600),
+        (Period::Monthly, 3, 7_776_000),
+        (Period::Yearly, 1, 31_536_000),
+    ];
+    for (p, n, want) in cases.iter() {
+        let id =
+            x.c.create_subscription_periodic(&u, &m, &x.tok, &10, p, n, &2000, &None, &None, &0);
+        assert_eq!(x.c.get_subscription(&id).interval_seconds, *want);
+    }
+    assert!(x
+        .c
+        .try_create_subscription_periodic(
+            &u,
+            &m,
+            &x.tok,
+            &10,
+            &Period::Daily,
+            &0,
+            &2000,
+            &None,
+            &None,
+            &0
+        )
+        .is_err());
+}
+
+#[test]
+fn safe_getters() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000);
+    assert!(x.c.get_stream_by_id_safe(&1).is_none());
+    assert!(x.c.get_subscription_by_id_safe(&1).is_none());
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &100,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(x.c.get_stream_by_id_safe(&id).unwrap().id, id);
+    let sid = mksub(&x, &s, &r, 10, 100, 2000);
+    assert_eq!(x.c.get_subscription_by_id_safe(&sid).unwrap().id, sid);
+    assert!(x.c.get_subscription_by_id_safe(&(sid + 1)).is_none());
+}
+
+#[test]
+fn withdraw_callback() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let v = x.env.register(vault::Vault, ());
+    let bad = x.env.register(vault::BadVault, ());
+    let vc = vault::VaultClient::new(&x.env, &v);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, v.clone(), bad.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 5);
+    x.c.withdraw_stream(&id, &v);
+    assert_eq!(vc.got(), None);
+    x.c.set_withdraw_notify(&id, &v, &true);
+    x.c.set_withdraw_notify(&id, &bad, &true);
+    adv(&x, 5);
+    assert_eq!(x.c.withdraw_stream(&id, &v), 50);
+    assert_eq!(vc.got(), Some((id, 50)));
+    assert_eq!(x.c.withdraw_stream(&id, &bad), 100);
+    assert_eq!(bal(&x, &bad), 100);
+    let stranger = Address::generate(&x.env);
+    assert_eq!(
+        x.c.try_set_withdraw_notify(&id, &stranger, &true),
+        Err(Ok(Error::NotARecipient))
+    );
+}
+
+#[test]
+fn cancel_policies() {
+    let x = setup();
+    x.c.set_max_streams_per_ledger(&0);
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 2_000);
+    let a = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let mut o = opts(None, None, None);
+    o.cancel_policy = CancelPolicy::RefundSender;
+    let b = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &o,
+    );
+    adv(&x, 10);
+    x.c.cancel_stream(&a);
+    assert_eq!(bal(&x, &r), 100);
+    assert_eq!(bal(&x, &s), 900);
+    x.c.cancel_stream(&b);
+    assert_eq!(bal(&x, &r), 100);
+    assert_eq!(bal(&x, &s), 1_900);
+}
+
+#[test]
+fn user_dashboard() {
+    let x = setup();
+    x.c.set_max_streams_per_ledger(&0);
+    let u = Address::generate(&x.env);
+    let o = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    mint(&x, &o, 10_000);
+    x.c.create_stream(
+        &u,
+        &vec![&x.env, o.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let b = x.c.create_stream(
+        &u,
+        &vec![&x.env, o.clone()],
+        &x.tok,
+        &vec![&x.env, 5i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.pause_stream(&b);
+    x.c.create_stream(
+        &o,
+        &vec![&x.env, u.clone()],
+        &x.tok,
+        &vec![&x.env, 7i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let s1 = mksub(&x, &u, &o, 10, 100, 2000);
+    let s2 = mksub(&x, &u, &o, 10, 100, 2000);
+    x.c.deposit_to_subscription(&s1, &300);
+    x.c.deposit_to_subscription(&s2, &200);
+    x.c.cancel_subscription(&s2);
+    let d = x.c.get_user_dashboard(&u);
+    assert_eq!((d.incoming_rate, d.outgoing_rate), (7 * S, 10 * S));
+    assert_eq!(
+        (
+            d.active_sent_streams,
+            d.active_received_streams,
+            d.active_subscriptions
+        ),
+        (2, 1, 1)
+    );
+    assert_eq!(d.subscriber_escrow, 300);
+    assert!(!d.truncated);
+}
+
+#[test]
+fn min_withdraw_interval() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let mut o = opts(None, None, None);
+    o.min_withdraw_interval = Some(100);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1i128],
+        &1,
+        &10_000,
+        &None,
+        &None,
+        &o,
+    );
+    adv(&x, 10);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 10);
+    assert_eq!(x.env.auths()[0].0, r);
+    // a sweep anyone can trigger pays out but doesn't restart the recipient's interval
+    adv(&x, 50);
+    assert_eq!(
+        x.c.withdraw_many(&vec![&x.env, id], &r),
+        vec![&x.env, (id, 50i128)]
+    );
+    adv(&x, 49);
+    assert_eq!(x.c.try_withdraw_stream(&id, &r), Err(Ok(Error::NotDueYet)));
+    adv(&x, 1);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 50);
+    adv(&x, 5);
+    // sender-driven settlement ignores the interval
+    assert_eq!(x.c.remove_recipient(&id, &r), 5);
+}
+
+#[test]
+fn charge_overflow_typed() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id = mksub(&x, &u, &m, 10, u64::MAX, 1000);
+    x.c.deposit_to_subscription(&id, &100);
+    assert_eq!(
+        x.c.try_charge_subscription(&id),
+        Err(Ok(Error::ArithmeticOverflow))
+    );
+    assert_eq!(x.c.get_subscription(&id).balance, 100);
+    let big = mksub(&x, &u, &m, i128::MAX / 2, 1, 1000);
+    adv(&x, 1000);
+    assert_eq!(
+        x.c.try_get_subscription_status(&big),
+        Err(Ok(Error::ArithmeticOverflow))
+    );
+    assert_eq!(
+        x.c.try_get_subscription_view(&big).err(),
+        Some(Ok(Error::ArithmeticOverflow))
+    );
+}
+
+#[test]
+fn recent_activity() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 1i128],
+        &1,
+        &100_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(x.c.get_recent_stream_activity(&id).len(), 0);
+    for i in 1..=12u64 {
+        adv(&x, i);
+        x.c.withdraw_stream(&id, &r);
+    }
+    let a = x.c.get_recent_stream_activity(&id);
+    assert_eq!(a.len(), 10);
+    assert_eq!(a.get(0).unwrap().amount, 3);
+    assert_eq!(a.get(9).unwrap().amount, 12);
+    assert_eq!(a.get(9).unwrap().timestamp, x.env.ledger().timestamp());
+    assert_eq!(a.get(9).unwrap().recipient, r);
+}
+
+#[test]
+fn frozen_token() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let sid = mksub(&x, &u, &m, 10, 100, 1100);
+    x.c.deposit_to_subscription(&sid, &500);
+    let st = x.c.create_stream(
+        &u,
+        &vec![&x.env, m.clone()],
+        &x.tok,
+        &vec![&x.env, 1i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 200);
+    x.c.freeze_token(&x.tok);
+    assert_eq!(
+        x.c.try_charge_subscription(&sid),
+        Err(Ok(Error::TokenFrozen))
+    );
+    assert_eq!(
+        x.c.try_withdraw_stream(&st, &m),
+        Err(Ok(Error::TokenFrozen))
+    );
+    // the freeze is checked before any accrual is worked out
+    assert_eq!(
+        x.c.try_withdraw_stream(&st, &u),
+        Err(Ok(Error::TokenFrozen))
+    );
+    assert_eq!(
+        x.c.try_create_stream(
+            &u,
+            &vec![&x.env, m.clone()],
+            &x.tok,
+            &vec![&x.env, 1i128],
+            &1,
+            &1_000,
+            &None,
+            &None,
+            &opts(None, None, None)
+        ),
+        Err(Ok(Error::TokenFrozen))
+    );
+    assert!(x.c.try_deposit_to_subscription(&sid, &1).is_err());
+    assert_eq!(
+        x.c.try_cancel_subscription(&sid),
+        Err(Ok(soroban_sdk::Error::from_contract_error(26)))
+    );
+    assert_eq!(
+        x.c.try_cancel_stream(&st),
+        Err(Ok(soroban_sdk::Error::from_contract_error(26)))
+    );
+    assert_eq!(
+        x.c.try_remove_recipient(&st, &m),
+        Err(Ok(Error::TokenFrozen))
+    );
+    assert_eq!(
+        x.c.try_cancel_all_sent_streams(&u, &0, &50),
+        Ok(Ok((0, false)))
+    );
+    assert_eq!(bal(&x, &u), 10_000 - 500 - 1_000);
+    x.c.unfreeze_token(&x.tok);
+    x.c.cancel_subscription(&sid);
+    assert_eq!(bal(&x, &u), 10_000 - 1_000);
+    assert_eq!(x.c.withdraw_stream(&st, &m), 200);
+}
+
+#[test]
+fn chargeable_helpers() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id = mksub(&x, &u, &m, 10, 100, 1100);
+    assert_eq!(x.c.get_next_charge_time(&id), 1100);
+    x.c.deposit_to_subscription(&id, &15);
+    assert!(!x.c.is_chargeable(&id)); // not yet due
+    adv(&x, 100);
+    assert!(x.c.is_chargeable(&id));
+    x.c.charge_subscription(&id);
+    assert_eq!(x.c.get_next_charge_time(&id), 1200);
+    adv(&x, 100);
+    assert!(!x.c.is_chargeable(&id)); // underfunded
+    assert!(!x.c.is_chargeable(&999));
+    assert_eq!(
+        x.c.try_get_next_charge_time(&999),
+        Err(Ok(Error::SubscriptionNotFound))
+    );
+}
+
+#[test]
+fn keeper_fee_paid() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    let k = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id = mksub(&x, &u, &m, 10, 100, 1100);
+    x.c.deposit_to_subscription(&id, &25);
+    x.c.set_keeper_fee(&id, &2);
+    adv(&x, 100);
+    assert_eq!(x.c.charge_subscription_as_keeper(&id, &k), 1);
+    assert_eq!(bal(&x, &k), 2);
+    assert_eq!(bal(&x, &m), 10);
+    assert_eq!(x.c.get_subscription(&id).balance, 13);
+    // 13 covers 10 + 2 once more, then 1 left can't cover anything
+    adv(&x, 100);
+    assert_eq!(x.c.charge_subscription_as_keeper(&id, &k), 1);
+    assert_eq!(bal(&x, &k), 4);
+    assert_eq!(bal(&x, &m), 20);
+    adv(&x, 100);
+    x.c.deposit_to_subscription(&id, &10);
+    // balance 11: 10 + fee 2 not covered
+    assert_eq!(x.c.charge_subscription_as_keeper(&id, &k), 0);
+    assert_eq!(bal(&x, &k), 4);
+    // plain charge pays no keeper fee
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    assert_eq!(bal(&x, &m), 30);
+    assert_eq!(x.c.get_subscription(&id).balance, 1);
+}
+
+#[test]
+fn recipients_list() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    let r3 = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let rs = vec![&x.env, r2.clone(), r1.clone(), r3.clone()];
+    let id = x.c.create_stream(
+        &s,
+        &rs,
+        &x.tok,
+        &vec![&x.env, 1i128, 2i128, 3i128],
+        &1,
+        &600,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(x.c.get_stream_recipients(&id), rs);
+    assert!(x.c.try_get_stream_recipients(&999).is_err());
+}
+
+#[test]
+fn finalize_exhausted() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 3i128, 7i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    adv(&x, 50);
+    assert_eq!(
+        x.c.try_finalize_stream(&id),
+        Err(Ok(Error::StreamStillActive))
+    );
+    x.c.withdraw_stream(&id, &r2);
+    adv(&x, 1_000);
+    assert_eq!(x.c.finalize_stream(&id), 300 + 700 - 350);
+    assert_eq!(bal(&x, &r1), 300);
+    assert_eq!(bal(&x, &r2), 700);
+    assert_eq!(bal(&x, &s), 9_000);
+    let st = x.c.get_stream(&id);
+    assert!(!st.is_active);
+    assert_eq!(st.deposit, 0);
+    assert_eq!(x.c.get_stream_dust(&id), 0);
+    assert_eq!(x.c.try_finalize_stream(&id), Err(Ok(Error::StreamInactive)));
+    assert_eq!(x.c.get_user_streams_by_token(&r1, &x.tok, &0, &10).len(), 0);
+    assert_eq!(x.c.get_user_streams_by_token(&s, &x.tok, &0, &10).len(), 0);
+
+    // end_time cut-off: unaccrued part goes back to the sender
+    let id2 = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 3i128, 7i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, Some(x.env.ledger().timestamp() + 10), None),
+    );
+    adv(&x, 20);
+    assert_eq!(x.c.finalize_stream(&id2), 100);
+    assert_eq!(bal(&x, &r1), 330);
+    assert_eq!(bal(&x, &r2), 770);
+    assert_eq!(bal(&x, &s), 9_000 - 100);
+}
+
+#[test]
+fn auto_refill() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let p = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 1_000);
+    mint(&x, &p, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert!(x
+        .c
+        .try_set_stream_refill(&id, &Some(p.clone()), &0)
+        .is_err());
+    x.c.set_stream_refill(&id, &Some(p.clone()), &500);
+    token::Client::new(&x.env, &x.tok).approve(&p, &x.c.address, &1_000, &10_000);
+    adv(&x, 60);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 600);
+    let stream = x.c.get_stream(&id);
+    assert_eq!(stream.deposit, 1_500);
+    assert_eq!(stream.recipient_allocation.get(r.clone()), Some(1_500));
+    assert_eq!(stream.recipient_total_withdrawn.get(r.clone()), Some(600));
+    assert_eq!(bal(&x, &x.c.address), 900);
+    adv(&x, 70);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 700);
+    assert_eq!(x.c.get_stream(&id).deposit, 2_000);
+    assert_eq!(bal(&x, &p), 9_000);
+    // allowance used up: the next low-water withdrawal can't refill and the stream winds down
+    adv(&x, 60);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 600);
+    assert_eq!(x.c.get_stream(&id).deposit, 2_000);
+    adv(&x, 100);
+    assert_eq!(x.c.withdraw_stream(&id, &r), 100);
+    assert!(!x.c.get_stream(&id).is_active);
+    assert_eq!(bal(&x, &r), 2_000);
+}
+
+#[test]
+fn receiver_active_count() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let a = mksub(&x, &u, &m, 10, 100, 1100);
+    let b = mksub(&x, &u, &m, 10, 100, 1100);
+    let _c = mksub(&x, &u, &m, 10, 100, 1100);
+    assert_eq!(x.c.get_rcvd_active_subs_count(&m, &0, &50), 3);
+    x.c.cancel_subscription(&a);
+    x.c.cancel_subscription(&b);
+    assert_eq!(x.c.get_rcvd_active_subs_count(&m, &0, &50), 1);
+    assert_eq!(x.c.get_rcvd_active_subs_count(&m, &0, &2), 0);
+    assert_eq!(x.c.get_rcvd_active_subs_count(&m, &2, &2), 1);
+}
+
+#[test]
+fn one_time_subscription() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id = mksub(&x, &u, &m, 10, 100, 1100);
+    x.c.set_max_charges(&id, &Some(1));
+    x.c.deposit_to_subscription(&id, &35);
+    adv(&x, 300); // three intervals overdue, but only one may be charged
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    let sub = x.c.get_subscription(&id);
+    assert!(!sub.active);
+    assert_eq!(sub.balance, 0);
+    assert_eq!(sub.charge_count, 1);
+    assert_eq!(bal(&x, &m), 10);
+    assert_eq!(bal(&x, &u), 10_000 - 10);
+    assert_eq!(
+        x.c.try_charge_subscription(&id),
+        Err(Ok(Error::SubscriptionInactive))
+    );
+    assert!(x.c.try_set_max_charges(&id, &Some(2)).is_err());
+
+    let id2 = mksub(&x, &u, &m, 10, 100, x.env.ledger().timestamp() + 100);
+    x.c.deposit_to_subscription(&id2, &20);
+    adv(&x, 100);
+    x.c.charge_subscription(&id2);
+    assert_eq!(
+        x.c.try_set_max_charges(&id2, &Some(1)),
+        Err(Ok(Error::InvalidParameters))
+    );
+    x.c.set_max_charges(&id2, &None);
+    adv(&x, 100);
+    x.c.charge_subscription(&id2);
+    assert!(x.c.get_subscription(&id2).active);
+}
+
+#[test]
+fn interval_edits_across_due_boundary() {
+    let x = setup();
+    let u = Address::generate(&x.env);
+    let m = Address::generate(&x.env);
+    mint(&x, &u, 10_000);
+    let id = mksub(&x, &u, &m, 10, 100, 1100);
+    x.c.deposit_to_subscription(&id, &1_000);
+    // shrink while a payment is due: the due one settles at the old interval first
+    adv(&x, 150);
+    x.c.update_subscription_interval(&id, &10);
+    assert_eq!(bal(&x, &m), 10);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1200);
+    assert_eq!(x.c.try_charge_subscription(&id), Err(Ok(Error::NotDueYet)));
+    adv(&x, 75); // 1225: 1200, 1210, 1220 due
+    assert_eq!(x.c.charge_subscription(&id), 3);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1230);
+    // grow right at the boundary
+    adv(&x, 5); // 1230
+    x.c.update_subscription_interval(&id, &1_000);
+    assert_eq!(bal(&x, &m), 50);
+    assert_eq!(x.c.get_subscription(&id).next_payment_time, 1240);
+    adv(&x, 10);
+    assert_eq!(x.c.charge_subscription(&id), 1);
+    // pause across a due point, resume, and the schedule stays on the new grid
+    x.c.pause_subscription(&id);
+    adv(&x, 2_500);
+    x.c.resume_subscription(&id);
+    let sub = x.c.get_subscription(&id);
+    assert!(sub.next_payment_time >= x.env.ledger().timestamp());
+    assert_eq!((sub.next_payment_time - 1240) % 1_000, 0);
+    assert_eq!(x.c.try_charge_subscription(&id), Err(Ok(Error::NotDueYet)));
+    assert_eq!(x.c.get_subscription_totals(&id).1, 6);
+}
+
+#[test]
+fn sender_obligations() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let tok2 = x
+        .env
+        .register_stellar_asset_contract_v2(x.admin.clone())
+        .address();
+    token::StellarAssetClient::new(&x.env, &tok2).mint(&s, &10_000);
+    x.c.allow_token(&tok2);
+    x.c.allow_token(&x.tok);
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 3i128, 7i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.create_stream(
+        &s,
+        &vec![&x.env, r2.clone()],
+        &tok2,
+        &vec![&x.env, 5i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    let d = x.c.create_stream(
+        &s,
+        &vec![&x.env, r2.clone()],
+        &x.tok,
+        &vec![&x.env, 50i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    x.c.cancel_stream(&d);
+    adv(&x, 10);
+    assert_eq!(x.c.get_sender_total_outflow_rate(&s, &x.tok, &0, &50), 20);
+    assert_eq!(
+        x.c.get_sender_outflow_rate_scaled(&s, &x.tok, &0, &50),
+        20 * S
+    );
+    assert_eq!(x.c.get_sender_total_remaining(&s, &x.tok, &0, &50), 1_800);
+    assert_eq!(x.c.get_sender_total_outflow_rate(&s, &tok2, &0, &50), 5);
+    assert_eq!(x.c.get_sender_total_outflow_rate(&s, &x.tok, &1, &1), 10);
+    assert_eq!(x.c.get_sender_total_remaining(&s, &x.tok, &0, &1), 900);
+}
+
+#[test]
+fn sub_unit_per_second_rates() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r1 = Address::generate(&x.env);
+    let r2 = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    // 1000 and 7 per hour: both under one unit per second
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r1.clone(), r2.clone()],
+        &x.tok,
+        &vec![&x.env, 1_000i128, 7i128],
+        &3_600,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    assert_eq!(x.c.get_recipient_rate(&id, &r1), (1_000, 3_600));
+    assert_eq!(
+        x.c.get_stream(&id)
+            .recipient_rate_per_second
+            .get(r1.clone()),
+        Some(0)
+    );
+    assert_eq!(x.c.get_recipient_active_stream_ids(&r1).len(), 1);
+    assert_eq!(
+        x.c.try_get_recipient_rate(&id, &s),
+        Err(Ok(Error::NotARecipient))
+    );
+    for step in [1_001u64, 999, 1_234, 366] {
+        adv(&x, step);
+        let _ = x.c.try_withdraw_stream(&id, &r1);
+        let _ = x.c.try_withdraw_stream(&id, &r2);
+    }
+    // exactly one period: no truncation loss
+    assert_eq!(bal(&x, &r1), 1_000);
+    assert_eq!(bal(&x, &r2), 7);
+    // per-minute recipient added to an hourly stream
+    x.c.add_recipient(&id, &s, &1, &60);
+    assert_eq!(x.c.get_recipient_rate(&id, &s), (60, 3_600));
+    assert_eq!(
+        x.c.try_add_recipient(&id, &Address::generate(&x.env), &1, &7_200),
+        Err(Ok(Error::RateTooSmall))
+    );
+}
+
+#[test]
+fn hourly_rate_in_aggregates() {
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 100_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 3_600i128 / 4],
+        &3_600,
+        &10_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    // The whole-unit getters round the quarter unit per second down to 0
+    assert_eq!(x.c.get_recipient_total_rate(&r, &x.tok), 0);
+    assert_eq!(x.c.get_recipient_total_rate_scaled(&r, &x.tok), S / 4);
+    assert_eq!(x.c.get_sender_total_outflow_rate(&s, &x.tok, &0, &50), 0);
+    assert_eq!(
+        x.c.get_sender_outflow_rate_scaled(&s, &x.tok, &0, &50),
+        S / 4
+    );
+    assert_eq!(x.c.get_stream_view(&id).total_outflow_rate, S / 4);
+    assert_eq!(x.c.get_user_dashboard(&s).outgoing_rate, S / 4);
+}
+
+#[test]
+fn withdraw_event_carries_cumulative() {
+    use soroban_sdk::testutils::Events as _;
+    use soroban_sdk::{IntoVal, TryFromVal, Val};
+    let x = setup();
+    let s = Address::generate(&x.env);
+    let r = Address::generate(&x.env);
+    mint(&x, &s, 10_000);
+    let id = x.c.create_stream(
+        &s,
+        &vec![&x.env, r.clone()],
+        &x.tok,
+        &vec![&x.env, 10i128],
+        &1,
+        &1_000,
+        &None,
+        &None,
+        &opts(None, None, None),
+    );
+    for step in [10u64, 25] {
+        adv(&x, step);
+        x.c.withdraw_stream(&id, &r);
+        let evs = x.env.events().all();
+        let mut found = false;
+        for (addr, topics, data) in evs.iter() {
+            if addr != x.c.address {
+                continue;
+            }
+            let t0: Val = topics.get(0).unwrap();
+            if soroban_sdk::Symbol::try_from_val(&x.env, &t0).ok()
+                != Some(soroban_sdk::symbol_short!("strm_wd"))
+            {
+                continue;
+            }
+            let (_, _, _, _, total): (Address, i128, u64, Address, i128) = data.into_val(&x.env);
+            assert_eq!(
+                Some(total),
+                x.c.get_stream(&id).recipient_total_withdrawn.get(r.clone())
+            );
+            found = true;
+        }
+        assert!(found);
+    }
+    assert_eq!(
+        x.c.get_stream(&id).recipient_total_withdrawn.get(r.clone()),
+        Some(350)
+    );
+}